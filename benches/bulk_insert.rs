@@ -0,0 +1,54 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+use segment_map::{
+    Segment,
+    SegmentMap,
+};
+
+const SEGMENTS: usize = 1_000_000;
+
+// counts allocations, to show how many the insertions themselves make
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn fill(segment_map: &mut SegmentMap<usize, usize>) {
+    // insert in a scattered order so the tree isn't degenerate
+    for i in 0..SEGMENTS {
+        let i = (i * 7919) % SEGMENTS;
+        segment_map.insert(Segment::new(2 * i, 2 * i + 1), i);
+    }
+}
+
+fn main() {
+    for (name, mut segment_map) in [
+        ("new", SegmentMap::new()),
+        ("with_capacity", SegmentMap::with_capacity(SEGMENTS)),
+    ] {
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        fill(&mut segment_map);
+        let elapsed = start.elapsed();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        black_box(segment_map);
+        println!("insert after {:<14} {:?}, {} allocations", format!("{}:", name), elapsed, allocations);
+    }
+}
@@ -0,0 +1,36 @@
+use std::{
+    hint::black_box,
+    time::Instant,
+};
+use segment_map::{
+    Segment,
+    SegmentMap,
+};
+
+const SEGMENTS: usize = 10_000;
+const WIDTH: usize = 16;
+
+fn main() {
+    // insert in a scattered order so the tree isn't degenerate
+    let mut segment_map = SegmentMap::new();
+    for i in 0..SEGMENTS {
+        let i = (i * 7919) % SEGMENTS;
+        segment_map.insert(Segment::new(i * WIDTH, i * WIDTH + WIDTH / 2), i);
+    }
+
+    let start = Instant::now();
+    for key in 0..(SEGMENTS * WIDTH) {
+        black_box(segment_map.get(&key));
+    }
+    let get = start.elapsed();
+
+    let start = Instant::now();
+    let mut cursor = segment_map.locality_cursor();
+    for key in 0..(SEGMENTS * WIDTH) {
+        black_box(cursor.get(&key));
+    }
+    let cursor = start.elapsed();
+
+    println!("monotonic get:    {:?}", get);
+    println!("monotonic cursor: {:?}", cursor);
+}
@@ -1,18 +1,3 @@
-use std::{
-    usize,
-    u8,
-    u16,
-    u32,
-    u64,
-    u128,
-    isize,
-    i8,
-    i16,
-    i32,
-    i64,
-    i128
-};
-
 pub trait Bounded {
     fn min() -> Self;
     fn max() -> Self;
@@ -77,3 +62,13 @@ impl Bounded for i128 {
     fn min() -> i128 { i128::MIN }
     fn max() -> i128 { i128::MAX }
 }
+
+impl Bounded for f32 {
+    fn min() -> f32 { f32::NEG_INFINITY }
+    fn max() -> f32 { f32::INFINITY }
+}
+
+impl Bounded for f64 {
+    fn min() -> f64 { f64::NEG_INFINITY }
+    fn max() -> f64 { f64::INFINITY }
+}
@@ -1,8 +1,15 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 mod segment;
 mod segment_map_node;
 mod segment_map;
 mod bounded;
 mod next;
+mod measure;
+mod total_segment_map;
+mod segment_set;
 
 pub use crate::segment_map::{
     SegmentMap,
@@ -11,8 +18,31 @@ pub use crate::segment_map::{
     ValuesMut,
     Iter,
     IterMut,
+    Range,
     IntoIter,
+    IntoIterRev,
+    IntoCoalesced,
+    IterCoalesced,
+    Gaps,
+    GapsWithin,
+    GapsWithNeighbors,
+    Span,
+    Spans,
+    Transitions,
+    RangeInfo,
+    Entry,
+    OccupiedEntry,
+    VacantEntry,
+    OccupiedError,
+    LocalityCursor,
+    Cursor,
+};
+pub use crate::total_segment_map::{
+    TotalSegmentMap,
+    IterDense,
 };
+pub use crate::segment_set::SegmentSet;
 pub use crate::segment::Segment;
 pub use crate::bounded::Bounded;
 pub use crate::next::Next;
+pub use crate::measure::Measure;
@@ -0,0 +1,153 @@
+use core::ops::Add;
+
+pub trait Measure: Sized + PartialOrd {
+    type Width: Clone + Default + PartialOrd + Add<Output = Self::Width>;
+
+    fn width_to(&self, upper: &Self) -> Self::Width;
+    fn add_width_checked(&self, width: &Self::Width) -> Option<Self>;
+    fn sub_width_checked(&self, width: &Self::Width) -> Option<Self>;
+    fn add_width_saturating(&self, width: &Self::Width) -> Self;
+    fn sub_width_saturating(&self, width: &Self::Width) -> Self;
+    fn add_width_unchecked(&self, width: &Self::Width) -> Self { self.add_width_checked(width).expect("overflow") }
+    fn sub_width_unchecked(&self, width: &Self::Width) -> Self { self.sub_width_checked(width).expect("overflow") }
+}
+
+impl Measure for usize {
+    type Width = usize;
+
+    fn width_to(&self, upper: &usize) -> usize { upper.saturating_sub(*self) }
+    fn add_width_checked(&self, width: &usize) -> Option<usize> { self.checked_add(*width) }
+    fn sub_width_checked(&self, width: &usize) -> Option<usize> { self.checked_sub(*width) }
+    fn add_width_saturating(&self, width: &usize) -> usize { self.saturating_add(*width) }
+    fn sub_width_saturating(&self, width: &usize) -> usize { self.saturating_sub(*width) }
+}
+
+impl Measure for u8 {
+    type Width = u8;
+
+    fn width_to(&self, upper: &u8) -> u8 { upper.saturating_sub(*self) }
+    fn add_width_checked(&self, width: &u8) -> Option<u8> { self.checked_add(*width) }
+    fn sub_width_checked(&self, width: &u8) -> Option<u8> { self.checked_sub(*width) }
+    fn add_width_saturating(&self, width: &u8) -> u8 { self.saturating_add(*width) }
+    fn sub_width_saturating(&self, width: &u8) -> u8 { self.saturating_sub(*width) }
+}
+
+impl Measure for u16 {
+    type Width = u16;
+
+    fn width_to(&self, upper: &u16) -> u16 { upper.saturating_sub(*self) }
+    fn add_width_checked(&self, width: &u16) -> Option<u16> { self.checked_add(*width) }
+    fn sub_width_checked(&self, width: &u16) -> Option<u16> { self.checked_sub(*width) }
+    fn add_width_saturating(&self, width: &u16) -> u16 { self.saturating_add(*width) }
+    fn sub_width_saturating(&self, width: &u16) -> u16 { self.saturating_sub(*width) }
+}
+
+impl Measure for u32 {
+    type Width = u32;
+
+    fn width_to(&self, upper: &u32) -> u32 { upper.saturating_sub(*self) }
+    fn add_width_checked(&self, width: &u32) -> Option<u32> { self.checked_add(*width) }
+    fn sub_width_checked(&self, width: &u32) -> Option<u32> { self.checked_sub(*width) }
+    fn add_width_saturating(&self, width: &u32) -> u32 { self.saturating_add(*width) }
+    fn sub_width_saturating(&self, width: &u32) -> u32 { self.saturating_sub(*width) }
+}
+
+impl Measure for u64 {
+    type Width = u64;
+
+    fn width_to(&self, upper: &u64) -> u64 { upper.saturating_sub(*self) }
+    fn add_width_checked(&self, width: &u64) -> Option<u64> { self.checked_add(*width) }
+    fn sub_width_checked(&self, width: &u64) -> Option<u64> { self.checked_sub(*width) }
+    fn add_width_saturating(&self, width: &u64) -> u64 { self.saturating_add(*width) }
+    fn sub_width_saturating(&self, width: &u64) -> u64 { self.saturating_sub(*width) }
+}
+
+impl Measure for u128 {
+    type Width = u128;
+
+    fn width_to(&self, upper: &u128) -> u128 { upper.saturating_sub(*self) }
+    fn add_width_checked(&self, width: &u128) -> Option<u128> { self.checked_add(*width) }
+    fn sub_width_checked(&self, width: &u128) -> Option<u128> { self.checked_sub(*width) }
+    fn add_width_saturating(&self, width: &u128) -> u128 { self.saturating_add(*width) }
+    fn sub_width_saturating(&self, width: &u128) -> u128 { self.saturating_sub(*width) }
+}
+
+impl Measure for isize {
+    type Width = usize;
+
+    fn width_to(&self, upper: &isize) -> usize { if self < upper { self.abs_diff(*upper) } else { 0 } }
+    fn add_width_checked(&self, width: &usize) -> Option<isize> { self.checked_add_unsigned(*width) }
+    fn sub_width_checked(&self, width: &usize) -> Option<isize> { self.checked_sub_unsigned(*width) }
+    fn add_width_saturating(&self, width: &usize) -> isize { self.saturating_add_unsigned(*width) }
+    fn sub_width_saturating(&self, width: &usize) -> isize { self.saturating_sub_unsigned(*width) }
+}
+
+impl Measure for i8 {
+    type Width = u8;
+
+    fn width_to(&self, upper: &i8) -> u8 { if self < upper { self.abs_diff(*upper) } else { 0 } }
+    fn add_width_checked(&self, width: &u8) -> Option<i8> { self.checked_add_unsigned(*width) }
+    fn sub_width_checked(&self, width: &u8) -> Option<i8> { self.checked_sub_unsigned(*width) }
+    fn add_width_saturating(&self, width: &u8) -> i8 { self.saturating_add_unsigned(*width) }
+    fn sub_width_saturating(&self, width: &u8) -> i8 { self.saturating_sub_unsigned(*width) }
+}
+
+impl Measure for i16 {
+    type Width = u16;
+
+    fn width_to(&self, upper: &i16) -> u16 { if self < upper { self.abs_diff(*upper) } else { 0 } }
+    fn add_width_checked(&self, width: &u16) -> Option<i16> { self.checked_add_unsigned(*width) }
+    fn sub_width_checked(&self, width: &u16) -> Option<i16> { self.checked_sub_unsigned(*width) }
+    fn add_width_saturating(&self, width: &u16) -> i16 { self.saturating_add_unsigned(*width) }
+    fn sub_width_saturating(&self, width: &u16) -> i16 { self.saturating_sub_unsigned(*width) }
+}
+
+impl Measure for i32 {
+    type Width = u32;
+
+    fn width_to(&self, upper: &i32) -> u32 { if self < upper { self.abs_diff(*upper) } else { 0 } }
+    fn add_width_checked(&self, width: &u32) -> Option<i32> { self.checked_add_unsigned(*width) }
+    fn sub_width_checked(&self, width: &u32) -> Option<i32> { self.checked_sub_unsigned(*width) }
+    fn add_width_saturating(&self, width: &u32) -> i32 { self.saturating_add_unsigned(*width) }
+    fn sub_width_saturating(&self, width: &u32) -> i32 { self.saturating_sub_unsigned(*width) }
+}
+
+impl Measure for i64 {
+    type Width = u64;
+
+    fn width_to(&self, upper: &i64) -> u64 { if self < upper { self.abs_diff(*upper) } else { 0 } }
+    fn add_width_checked(&self, width: &u64) -> Option<i64> { self.checked_add_unsigned(*width) }
+    fn sub_width_checked(&self, width: &u64) -> Option<i64> { self.checked_sub_unsigned(*width) }
+    fn add_width_saturating(&self, width: &u64) -> i64 { self.saturating_add_unsigned(*width) }
+    fn sub_width_saturating(&self, width: &u64) -> i64 { self.saturating_sub_unsigned(*width) }
+}
+
+impl Measure for i128 {
+    type Width = u128;
+
+    fn width_to(&self, upper: &i128) -> u128 { if self < upper { self.abs_diff(*upper) } else { 0 } }
+    fn add_width_checked(&self, width: &u128) -> Option<i128> { self.checked_add_unsigned(*width) }
+    fn sub_width_checked(&self, width: &u128) -> Option<i128> { self.checked_sub_unsigned(*width) }
+    fn add_width_saturating(&self, width: &u128) -> i128 { self.saturating_add_unsigned(*width) }
+    fn sub_width_saturating(&self, width: &u128) -> i128 { self.saturating_sub_unsigned(*width) }
+}
+
+impl Measure for f32 {
+    type Width = f32;
+
+    fn width_to(&self, upper: &f32) -> f32 { (upper - self).max(0.0) }
+    fn add_width_checked(&self, width: &f32) -> Option<f32> { Some(self + width) }
+    fn sub_width_checked(&self, width: &f32) -> Option<f32> { Some(self - width) }
+    fn add_width_saturating(&self, width: &f32) -> f32 { self + width }
+    fn sub_width_saturating(&self, width: &f32) -> f32 { self - width }
+}
+
+impl Measure for f64 {
+    type Width = f64;
+
+    fn width_to(&self, upper: &f64) -> f64 { (upper - self).max(0.0) }
+    fn add_width_checked(&self, width: &f64) -> Option<f64> { Some(self + width) }
+    fn sub_width_checked(&self, width: &f64) -> Option<f64> { Some(self - width) }
+    fn add_width_saturating(&self, width: &f64) -> f64 { self + width }
+    fn sub_width_saturating(&self, width: &f64) -> f64 { self - width }
+}
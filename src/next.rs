@@ -50,3 +50,34 @@ impl Next for i64 {
 impl Next for i128 {
     fn next_checked(&self) -> Option<i128> { self.checked_add(1) }
 }
+
+// floats are discrete too, so the next value is the next representable one
+impl Next for f32 {
+    fn next_checked(&self) -> Option<f32> {
+        if self.is_nan() || (*self == f32::INFINITY) {
+            None
+        // both zeros step to the smallest positive subnormal
+        } else if *self == 0.0 {
+            Some(f32::from_bits(1))
+        } else if *self > 0.0 {
+            Some(f32::from_bits(self.to_bits() + 1))
+        } else {
+            Some(f32::from_bits(self.to_bits() - 1))
+        }
+    }
+}
+
+impl Next for f64 {
+    fn next_checked(&self) -> Option<f64> {
+        if self.is_nan() || (*self == f64::INFINITY) {
+            None
+        // both zeros step to the smallest positive subnormal
+        } else if *self == 0.0 {
+            Some(f64::from_bits(1))
+        } else if *self > 0.0 {
+            Some(f64::from_bits(self.to_bits() + 1))
+        } else {
+            Some(f64::from_bits(self.to_bits() - 1))
+        }
+    }
+}
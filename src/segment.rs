@@ -1,7 +1,10 @@
 use crate::{
     Bounded,
+    Measure,
     Next,
 };
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Segment<K> {
@@ -9,6 +12,16 @@ pub struct Segment<K> {
     upper: K,
 }
 
+impl<K> Segment<K> {
+    pub fn lower(&self) -> &K {
+        &self.lower
+    }
+
+    pub fn upper(&self) -> &K {
+        &self.upper
+    }
+}
+
 impl<K> Segment<K> 
 where
     K: PartialOrd
@@ -29,29 +42,37 @@ where
         (self.lower <= other.lower) && (other.upper <= self.upper)
     }
 
+    /// Returns whether the segments share at least one point. Segments that only touch at an
+    /// endpoint, or that are empty, do not intersect.
+    pub fn intersects(&self, other: &Segment<K>) -> bool {
+        !self.is_empty() && !other.is_empty() && (self.lower < other.upper) && (other.lower < self.upper)
+    }
+
     pub fn is_connected(&self, other: &Segment<K>) -> bool {
         (self.lower <= other.upper) && (other.lower <= self.upper)
     }
 
+    /// Returns whether the segment contains no points, i.e. `lower == upper`.
+    ///
+    /// Empty segments can still be stored in a `SegmentMap` as zero-width markers between or at the
+    /// ends of other segments, but they never overlap anything.
     pub fn is_empty(&self) -> bool {
         self.lower == self.upper
     }
-
-    pub fn lower(&self) -> &K {
-        &self.lower
-    }
-
-    pub fn upper(&self) -> &K {
-        &self.upper
-    }
 }
 
 impl<K> Segment<K> 
 where
     K: Clone + PartialOrd
 {
+    /// Returns the points the segments share, or `None` if there are none, as for segments that
+    /// only touch at an endpoint.
+    ///
+    /// Up to 0.1.1, segments that only touched, or an empty segment within another, gave
+    /// `Some` empty segment rather than `None`. Callers relying on that should test
+    /// `is_connected` instead.
     pub fn intersection(&self, other: &Segment<K>) -> Option<Segment<K>> {
-        if self.is_connected(other) {
+        if self.intersects(other) {
             Some(Segment {
                 lower: if self.lower < other.lower { other.lower.clone() } else { self.lower.clone() },
                 upper: if other.upper < self.upper { other.upper.clone() } else { self.upper.clone() },
@@ -59,6 +80,19 @@ where
         } else { None }
     }
 
+    /// Returns the portion of the segment within `bounds`, or `None` if they share no points.
+    pub fn clamp_to(&self, bounds: &Segment<K>) -> Option<Segment<K>> {
+        self.intersection(bounds)
+    }
+
+    /// Returns the merged segment if the segments overlap or touch, or `None` if there is a gap
+    /// between them. Touching segments merge since their combined coverage is contiguous.
+    pub fn union(&self, other: &Segment<K>) -> Option<Segment<K>> {
+        if self.is_connected(other) {
+            Some(self.span(other))
+        } else { None }
+    }
+
     pub fn span(&self, other: &Segment<K>) -> Segment<K> {
         Segment {
             lower: if self.lower < other.lower { self.lower.clone() } else { other.lower.clone() },
@@ -105,15 +139,19 @@ where
         Segment { lower: K::min(), upper: value.next_unchecked() }
     }
 
+    /// Like `at_least`, `K::max()` itself is not covered.
     pub fn greater_than(value: K) -> Segment<K> {
         Segment { lower: value.next_unchecked(), upper: K::max() }
     }
 }
 
+// there is no unbounded endpoint, so these stand in with `K::min()` and `K::max()`
 impl<K> Segment<K>
 where
     K: Bounded + PartialOrd
 {
+    /// Returns `[value, K::max())`. Segments are half-open, so `K::max()` itself is not covered
+    /// (e.g. `i32::MAX` for `i32`). For floats, `K::max()` is infinity, so every finite key is.
     pub fn at_least(value: K) -> Segment<K> {
         Segment { lower: value, upper: K::max() }
     }
@@ -122,11 +160,53 @@ where
         Segment { lower: K::min(), upper: value }
     }
 
+    /// Like `at_least`, `K::max()` itself is not covered.
     pub fn all() -> Segment<K> {
         Segment { lower: K::min(), upper: K::max() }
     }
 }
 
+impl<K> Segment<K>
+where
+    K: Measure
+{
+    pub fn width(&self) -> K::Width {
+        self.lower.width_to(&self.upper)
+    }
+
+    /// Same as `width`.
+    pub fn length(&self) -> K::Width {
+        self.width()
+    }
+}
+
+
+#[cfg(feature = "serde")]
+impl<K> Serialize for Segment<K>
+where
+    K: Serialize
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // serialized as a compact [lower, upper] pair
+        (&self.lower, &self.upper).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K> Deserialize<'de> for Segment<K>
+where
+    K: Deserialize<'de> + PartialOrd
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Segment<K>, D::Error> {
+        let (lower, upper) = <(K, K)>::deserialize(deserializer)?;
+        if lower <= upper {
+            Ok(Segment { lower, upper })
+        } else {
+            Err(de::Error::custom("segment lower bound must not exceed upper bound"))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::Segment;
@@ -159,6 +239,54 @@ mod tests {
         assert!(!Segment::new(5, 11).contains(&14));
     }
 
+    #[test]
+    fn test_closed_open_float() {
+        // -----[-----]----- contains both endpoints
+        let closed = Segment::closed(0.5, 1.5);
+        assert!(closed.contains(&0.5));
+        assert!(closed.contains(&1.5));
+        assert!(!closed.contains(&1.500_000_000_000_000_2));
+
+        // -----(-----)----- contains neither endpoint
+        let open = Segment::open(0.5f32, 1.5);
+        assert!(!open.contains(&0.5));
+        assert!(open.contains(&0.500_000_06));
+        assert!(!open.contains(&1.5));
+
+        // across zero and negatives
+        assert!(Segment::closed(-1.0, 0.0).contains(&0.0));
+        assert!(Segment::closed(-1.0, -0.5).contains(&-0.5));
+        assert!(!Segment::open(-1.0, 0.0).contains(&-1.0));
+        assert!(!Segment::closed(-1.0, 0.0).intersects(&Segment::open(0.0, 1.0)));
+        assert!(Segment::closed(-1.0, 0.0).intersects(&Segment::closed(0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_width() {
+        assert_eq!(6u32, Segment::new(5, 11).width());
+        assert_eq!(0u32, Segment::new(5, 5).width());
+        assert_eq!(255, Segment::new(i8::MIN, i8::MAX).width());
+        assert_eq!(u64::MAX, Segment::new(i64::MIN, i64::MAX).width());
+        assert_eq!(0u32, Segment::new(3, -3).width());
+        assert_eq!(2.5, Segment::new(0.5, 3.0).width());
+    }
+
+    #[test]
+    fn test_is_empty() {
+        assert!(Segment::new(0, 0).is_empty());
+        assert!(Segment::new(6, 6).is_empty());
+        assert!(!Segment::new(0, 6).is_empty());
+        assert!(!Segment::new(6, 6).contains(&6));
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(6u32, Segment::new(5, 11).length());
+        assert_eq!(0u32, Segment::new(5, 5).length());
+        assert_eq!(6u32, Segment::new(-11, -5).length());
+        assert_eq!(2.5, Segment::new(0.5, 3.0).length());
+    }
+
     #[test]
     fn test_encloses() {
         // -----[-----)-----
@@ -275,14 +403,19 @@ mod tests {
         assert_eq!(Some(Segment::new(7, 11)), Segment::new(7, 13).intersection(&Segment::new(5, 11)));
 
         // --------[-----)--
-        //                   -> -----------------
+        //                   -> None
         // --[-----)--------
-        assert_eq!(Some(Segment::new(8, 8)), Segment::new(8, 14).intersection(&Segment::new(2, 8)));
+        assert_eq!(None, Segment::new(8, 14).intersection(&Segment::new(2, 8)));
 
         // --[-----)--------
-        //                   -> -----------------
+        //                   -> None
         // --------[-----)--
-        assert_eq!(Some(Segment::new(8, 8)), Segment::new(2, 8).intersection(&Segment::new(8, 14)));
+        assert_eq!(None, Segment::new(2, 8).intersection(&Segment::new(8, 14)));
+
+        // --------[--------
+        //                   -> None
+        // -----[-----)-----
+        assert_eq!(None, Segment::new(8, 8).intersection(&Segment::new(5, 11)));
 
         // ----------[-----)
         //                   -> None
@@ -295,6 +428,72 @@ mod tests {
         assert_eq!(None, Segment::new(0, 6).intersection(&Segment::new(10, 16)));
     }
 
+    #[test]
+    fn test_intersects() {
+        // -----[-----)-----
+        //                   -> true
+        // -------[-----)---
+        assert!(Segment::new(5, 11).intersects(&Segment::new(7, 13)));
+
+        // -----[-----)-----
+        //                   -> true
+        // -------[-)-------
+        assert!(Segment::new(5, 11).intersects(&Segment::new(7, 9)));
+
+        // --------[-----)--
+        //                   -> false
+        // --[-----)--------
+        assert!(!Segment::new(8, 14).intersects(&Segment::new(2, 8)));
+
+        // --[-----)--------
+        //                   -> false
+        // --------[-----)--
+        assert!(!Segment::new(2, 8).intersects(&Segment::new(8, 14)));
+
+        // -----[-----)-----
+        //                   -> false
+        // --------|--------
+        assert!(!Segment::new(5, 11).intersects(&Segment::new(8, 8)));
+
+        // [-----)----------
+        //                   -> false
+        // ----------[-----)
+        assert!(!Segment::new(0, 6).intersects(&Segment::new(10, 16)));
+    }
+
+    #[test]
+    fn test_clamp_to() {
+        assert_eq!(Some(Segment::new(6, 12)), Segment::new(0, 18).clamp_to(&Segment::new(6, 12)));
+        assert_eq!(Some(Segment::new(6, 12)), Segment::new(6, 12).clamp_to(&Segment::new(0, 18)));
+        assert_eq!(Some(Segment::new(6, 9)), Segment::new(0, 9).clamp_to(&Segment::new(6, 12)));
+        assert_eq!(None, Segment::new(0, 6).clamp_to(&Segment::new(6, 12)));
+        assert_eq!(None, Segment::new(0, 6).clamp_to(&Segment::new(10, 16)));
+        assert_eq!(None, Segment::new(8, 8).clamp_to(&Segment::new(6, 12)));
+    }
+
+    #[test]
+    fn test_union() {
+        // -----[-----)-----
+        //                   -> -----[-------)---
+        // -------[-----)---
+        assert_eq!(Some(Segment::new(5, 13)), Segment::new(5, 11).union(&Segment::new(7, 13)));
+
+        // -----[-----)-----
+        //                   -> -----[-----)-----
+        // -------[-)-------
+        assert_eq!(Some(Segment::new(5, 11)), Segment::new(5, 11).union(&Segment::new(7, 9)));
+
+        // --------[-----)--
+        //                   -> --[-----------)--
+        // --[-----)--------
+        assert_eq!(Some(Segment::new(2, 14)), Segment::new(8, 14).union(&Segment::new(2, 8)));
+
+        // [-----)----------
+        //                   -> None
+        // ----------[-----)
+        assert_eq!(None, Segment::new(0, 6).union(&Segment::new(10, 16)));
+    }
+
     #[test]
     fn test_is_connected() {
         // -----[-----)-----
@@ -430,4 +629,14 @@ mod tests {
         // ----------[-----)
         assert_eq!(Segment::new(0, 16), Segment::new(0, 6).span(&Segment::new(10, 16)));
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        assert_eq!("[5,11]", serde_json::to_string(&Segment::new(5, 11)).unwrap());
+        assert_eq!(Segment::new(5, 11), serde_json::from_str::<Segment<i32>>("[5,11]").unwrap());
+        assert_eq!(Segment::new(5, 5), serde_json::from_str::<Segment<i32>>("[5,5]").unwrap());
+        assert!(serde_json::from_str::<Segment<i32>>("[11,5]").is_err());
+        assert!(serde_json::from_str::<Segment<i32>>("{\"lower\":5,\"upper\":11}").is_err());
+    }
 }
@@ -1,11 +1,116 @@
+use core::{
+    borrow::Borrow,
+    cell::Cell,
+    fmt,
+    cmp::{Ordering, Reverse},
+    hash::{Hash, Hasher},
+    iter::Sum,
+    mem,
+};
+use core::ops::{
+    Add,
+    Index,
+    Sub,
+};
+use alloc::{
+    boxed::Box,
+    collections::{BinaryHeap, VecDeque},
+    vec,
+    vec::Vec,
+};
+use alloc::{
+    format,
+    string::String,
+};
+#[cfg(feature = "testing")]
+use alloc::string::ToString;
+#[cfg(feature = "std")]
+use std::{
+    collections::HashSet,
+    error::Error,
+};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use crate::{
-    segment_map_node::SegmentMapNode,
+    segment_map_node::{Links, SegmentMapNode},
+    Measure,
     Segment,
 };
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// whether `entries` could be the contents of a map, in order
+fn is_sorted_disjoint<K, V>(entries: &[(Segment<K>, V)]) -> bool
+where
+    K: PartialOrd
+{
+    entries.windows(2).all(|pair| (pair[0].0.upper() <= pair[1].0.lower()) && (pair[0].0 != pair[1].0))
+}
+
+// the distinct bounds of `segments`, in order, strictly between `lower` and `upper`
+fn boundaries_within<'a, K>(
+    segments: impl Iterator<Item = &'a Segment<K>>,
+    lower: &'a K,
+    upper: &'a K,
+) -> impl Iterator<Item = &'a K>
+where
+    K: PartialOrd + 'a
+{
+    segments
+        .flat_map(|segment| [segment.lower(), segment.upper()])
+        .filter(move |&boundary| (lower < boundary) && (boundary < upper))
+        .scan(None, |previous, boundary| {
+            let repeated = *previous == Some(boundary);
+            *previous = Some(boundary);
+            Some(if repeated { None } else { Some(boundary) })
+        })
+        .flatten()
+}
+
 pub struct SegmentMap<K, V> {
     root: Option<SegmentMapNode<K, V>>,
+    len: usize,
+    // links of removed or preallocated nodes, reused before allocating new ones
+    spare: Vec<Links<K, V>>,
+}
+
+impl<K, V> SegmentMap<K, V> {
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            frames: self.root.as_ref().map(IterFrame::Node).into_iter().collect(),
+            remaining: self.len,
+        }
+    }
+
+    /// Moves the entries into a `Vec` in ascending order.
+    pub fn into_vec(self) -> Vec<(Segment<K>, V)> {
+        self.into_iter().collect()
+    }
+
+    /// Transforms every value, keeping the segments (and the shape of the tree) as they are.
+    /// Values are visited in ascending order.
+    pub fn map_values<W, F>(self, mut f: F) -> SegmentMap<K, W>
+    where
+        F: FnMut(V) -> W
+    {
+        SegmentMap {
+            root: self.root.map(|root| root.map_values(&mut f)),
+            len: self.len,
+            spare: Vec::new(),
+        }
+    }
+
+    pub fn map_values_ref<W, F>(&self, mut f: F) -> SegmentMap<K, W>
+    where
+        K: Clone,
+        F: FnMut(&V) -> W
+    {
+        SegmentMap {
+            root: self.root.as_ref().map(|root| root.map_values_ref(&mut f)),
+            len: self.len,
+            spare: Vec::new(),
+        }
+    }
 }
 
 impl<K, V> SegmentMap<K, V> 
@@ -13,7 +118,40 @@ where
     K: PartialOrd
 {
     pub fn new() -> SegmentMap<K, V> {
-        SegmentMap { root: None }
+        SegmentMap { root: None, len: 0, spare: Vec::new() }
+    }
+
+    /// Creates an empty map which can hold `capacity` segments before allocating again.
+    ///
+    /// Every node is allocated up front rather than one at a time as segments are inserted, which
+    /// takes the allocator out of bulk loads. Nodes are only reused by `insert` and the like;
+    /// operations rebuilding the whole map allocate as usual.
+    pub fn with_capacity(capacity: usize) -> SegmentMap<K, V> {
+        SegmentMap {
+            root: None,
+            len: 0,
+            spare: (0..capacity).map(|_| (Box::new(None), Box::new(None))).collect(),
+        }
+    }
+
+    /// Returns how many segments the map can hold before allocating again.
+    pub fn capacity(&self) -> usize {
+        self.len + self.spare.len()
+    }
+
+    /// Builds a balanced map in linear time from segments that are already in ascending order and
+    /// do not overlap.
+    ///
+    /// In debug builds, panics if the segments are out of order or overlap.
+    pub fn from_sorted<I>(entries: I) -> SegmentMap<K, V>
+    where
+        I: IntoIterator<Item = (Segment<K>, V)>
+    {
+        let entries = entries.into_iter().collect::<Vec<_>>();
+        debug_assert!(is_sorted_disjoint(&entries), "segments must be sorted and must not overlap");
+        let mut segment_map = SegmentMap::new();
+        segment_map.rebuild(entries);
+        segment_map
     }
 
     pub fn segments(&self) -> Segments<'_, K, V> {
@@ -24,55 +162,350 @@ where
         Values { inner: self.iter() }
     }
 
+    /// Iterates mutably over the values in ascending segment order, so a running transformation
+    /// (such as a prefix sum) is applied left to right. Use `rev` to go right to left.
     pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
         ValuesMut { inner: self.iter_mut() }
     }
 
-    pub fn iter(&self) -> Iter<'_, K, V> {
-        Iter {
-            current: self.root.as_ref(),
-            stack: Vec::new(),
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            frames: self.root.as_mut().map(IterMutFrame::Node).into_iter().collect(),
+            remaining: self.len,
         }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
-        IterMut {
-            current: self.root.as_mut(),
+    /// Iterates over the entries overlapping `[lo, hi)`, yielding nothing if `lo >= hi`.
+    pub fn iter_between<'a>(&'a self, lo: &'a K, hi: &'a K) -> impl Iterator<Item = (&'a Segment<K>, &'a V)> + 'a {
+        self.iter_from(|segment| segment.upper() <= lo)
+            .take_while(move |(segment, _)| (lo < hi) && (segment.lower() < hi))
+            .filter(|(segment, _)| !segment.is_empty())
+    }
+
+    // iterates over the entries after those satisfying `is_before`, which must be true for some
+    // prefix of the entries and false for the rest
+    fn iter_from<F>(&self, is_before: F) -> Iter<'_, K, V>
+    where
+        F: Fn(&Segment<K>) -> bool
+    {
+        let mut frames = VecDeque::new();
+        let mut node = self.root.as_ref();
+        while let Some(current) = node {
+            // if current is skipped, so is its left, look right
+            if is_before(&current.segment) {
+                node = (*current.right).as_ref();
+            // otherwise, current (then its right) is yielded, look left for earlier entries
+            } else {
+                if let Some(right) = (*current.right).as_ref() {
+                    frames.push_front(IterFrame::Node(right));
+                }
+                frames.push_front(IterFrame::Entry(&current.segment, &current.value));
+                node = (*current.left).as_ref();
+            }
+        }
+        // the skipped entries aren't counted, so the remaining count only bounds what is left
+        Iter { frames, remaining: self.len }
+    }
+
+    /// Iterates over the entries overlapping `query` in ascending order.
+    ///
+    /// An empty `query` is treated as a point, matching only the segment containing it (as
+    /// `get_entry` would). Empty segments never overlap anything, so they are never yielded.
+    pub fn range<'a>(&'a self, query: &'a Segment<K>) -> Range<'a, K, V> {
+        Range {
+            current: self.root.as_ref(),
             stack: Vec::new(),
+            query,
         }
     }
 
+    /// Iterates over the entries whose segments have a nonempty intersection with `segment`.
+    ///
+    /// Unlike `range`, an empty `segment` overlaps nothing.
+    pub fn overlapping<'a>(&'a self, segment: &'a Segment<K>) -> impl Iterator<Item = (&'a Segment<K>, &'a V)> + 'a {
+        self.range(segment).filter(move |_| !segment.is_empty())
+    }
+
+    pub fn into_iter_rev(self) -> IntoIterRev<K, V> {
+        IntoIterRev { inner: self.into_iter() }
+    }
+
     pub fn span(&self) -> Option<Segment<&K>> {
         self.root.as_ref().map(|root| root.span())
     }
 
+    pub fn key_range(&self) -> Option<(&K, &K)> {
+        self.root.as_ref().map(|root| (root.min_key(), root.max_key()))
+    }
+
+    pub fn first_key_value(&self) -> Option<(&Segment<K>, &V)> {
+        self.root.as_ref().map(|root| root.min_node()).map(|node| (&node.segment, &node.value))
+    }
+
+    pub fn last_key_value(&self) -> Option<(&Segment<K>, &V)> {
+        self.root.as_ref().map(|root| root.max_node()).map(|node| (&node.segment, &node.value))
+    }
+
+    pub fn first_entry_mut(&mut self) -> Option<(&Segment<K>, &mut V)> {
+        self.root.as_mut().map(|root| root.min_node_mut()).map(|node| (&node.segment, &mut node.value))
+    }
+
+    pub fn last_entry_mut(&mut self) -> Option<(&Segment<K>, &mut V)> {
+        self.root.as_mut().map(|root| root.max_node_mut()).map(|node| (&node.segment, &mut node.value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.root.is_none()
+        self.len == 0
     }
 
+    /// Removes every entry, but keeps the nodes they were stored in for later insertions, so a map
+    /// refilled to a similar size (e.g. once per frame) doesn't allocate again.
+    ///
+    /// The nodes stay allocated until the map is dropped or `shrink_to_fit` is called, so clearing
+    /// a map that was once large holds on to that much memory.
     pub fn clear(&mut self) {
-        self.root = None;
+        if let Some(root) = self.root.take() {
+            root.release(&mut self.spare);
+        }
+        self.len = 0;
+    }
+
+    /// Frees the nodes kept by `clear` or `with_capacity` that hold no entry.
+    pub fn shrink_to_fit(&mut self) {
+        self.spare = Vec::new();
+    }
+
+    /// Removes every entry, yielding them in ascending order.
+    pub fn drain(&mut self) -> IntoIter<K, V> {
+        mem::take(self).into_iter()
+    }
+
+    pub fn replace_with(&mut self, other: SegmentMap<K, V>) -> SegmentMap<K, V> {
+        mem::replace(self, other)
     }
 
-    pub fn get(&self, key: &K) -> Option<&V> {
+    /// Returns the value of the segment containing `key`. The key may be any borrowed form of the
+    /// map's key type, but its ordering must agree with that of the key type.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
         self.root.as_ref().and_then(|root| root.get(key))
     }
 
-    pub fn get_entry(&self, key: &K) -> Option<(&Segment<K>, &V)> {
+    pub fn get_entry<Q>(&self, key: &Q) -> Option<(&Segment<K>, &V)>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
         self.root.as_ref().and_then(|root| root.get_entry(key))
     }
 
-    pub fn contains_key(&self, key: &K) -> bool {
+    /// Same as `get_entry`, under the name `BTreeMap` uses.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&Segment<K>, &V)>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
+        self.get_entry(key)
+    }
+
+    /// Returns the entry with the greatest lower bound at or before `key`, whether or not it
+    /// contains `key`.
+    pub fn floor_entry(&self, key: &K) -> Option<(&Segment<K>, &V)> {
+        self.root.as_ref().and_then(|root| root.floor_entry(key))
+    }
+
+    /// Returns the entry with the least lower bound at or after `key`.
+    pub fn ceiling_entry(&self, key: &K) -> Option<(&Segment<K>, &V)> {
+        self.root.as_ref().and_then(|root| root.ceiling_entry(key))
+    }
+
+    /// Returns the first entry for which `pred` is false, assuming `pred` holds for some prefix of
+    /// the entries (in ascending order) and fails for the rest, like `slice::partition_point`.
+    pub fn partition_point<F>(&self, pred: F) -> Option<(&Segment<K>, &V)>
+    where
+        F: Fn(&Segment<K>) -> bool
+    {
+        self.root.as_ref().and_then(|root| root.first_where(|segment| !pred(segment)))
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
+        self.root.as_mut().and_then(|root| root.get_mut(key))
+    }
+
+    pub fn get_entry_mut<Q>(&mut self, key: &Q) -> Option<(&Segment<K>, &mut V)>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
+        self.root.as_mut().and_then(|root| root.get_entry_mut(key))
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
         self.get_entry(key).is_some()
     }
 
-    pub fn insert(&mut self, segment: Segment<K>, value: V) {
-        if let Some(root) = self.root.as_mut() {
-            root.insert(segment, value);
-        } else {
-            self.root = Some(SegmentMapNode::new(segment, value, None, None));
+    /// Returns a cursor positioned before the first segment starting at or after `key`. A segment
+    /// containing `key` but starting before it is behind the cursor.
+    pub fn lower_bound(&self, key: &K) -> Cursor<'_, K, V> {
+        Cursor::new(self.root.as_ref(), |segment| segment.lower() < key)
+    }
+
+    /// Returns a cursor positioned before the first segment starting after `key`.
+    pub fn upper_bound(&self, key: &K) -> Cursor<'_, K, V> {
+        Cursor::new(self.root.as_ref(), |segment| segment.lower() <= key)
+    }
+
+    pub fn locality_cursor(&self) -> LocalityCursor<'_, K, V> {
+        let mut iter = self.iter();
+        LocalityCursor {
+            root: self.root.as_ref(),
+            len: self.len,
+            current: None,
+            next: iter.next(),
+            iter,
+        }
+    }
+
+    /// Returns a point evaluator equivalent to `get`, borrowing the map immutably.
+    ///
+    /// The evaluator remembers the last segment it hit and checks it before descending from the
+    /// root, so runs of queries falling within the same segment are answered without a descent.
+    pub fn evaluator<'a>(&'a self) -> impl Fn(&K) -> Option<&'a V> + 'a {
+        let last: Cell<Option<(&Segment<K>, &V)>> = Cell::new(None);
+        move |key| {
+            // if key falls in the last hit segment, skip the descent
+            if let Some((segment, value)) = last.get() {
+                if segment.contains(key) {
+                    return Some(value);
+                }
+            }
+            let entry = self.get_entry(key);
+            if entry.is_some() {
+                last.set(entry);
+            }
+            entry.map(|(_, value)| value)
+        }
+    }
+
+    pub fn is_partition_of(&self, bounds: &Segment<K>) -> bool {
+        if let Some(span) = self.span() {
+            // if span does not exactly match bounds, not a partition
+            if (*span.lower() != bounds.lower()) || (*span.upper() != bounds.upper()) {
+                return false;
+            }
+            // each segment must pick up exactly where the last left off
+            let mut segments = self.segments();
+            let mut covered = segments.next().map(|segment| segment.upper());
+            for segment in segments {
+                if Some(segment.lower()) != covered {
+                    return false;
+                }
+                covered = Some(segment.upper());
+            }
+            true
+        } else { bounds.is_empty() }
+    }
+
+    pub fn is_aligned_with<'a, W>(&'a self, other: &'a SegmentMap<K, W>) -> bool {
+        if let (Some(span), Some(other_span)) = (self.span(), other.span()) {
+            // only boundaries strictly within the common span are compared
+            let lower = if span.lower() < other_span.lower() { *other_span.lower() } else { *span.lower() };
+            let upper = if other_span.upper() < span.upper() { *other_span.upper() } else { *span.upper() };
+            // walk both maps in lock step, stopping at the first differing boundary
+            boundaries_within(self.iter().map(|(segment, _)| segment), lower, upper)
+                .eq(boundaries_within(other.iter().map(|(segment, _)| segment), lower, upper))
+        } else { true }
+    }
+
+    pub fn count_in(&self, range: &Segment<K>) -> usize {
+        self.root.as_ref().map_or(0, |root| root.count_enclosed(range))
+    }
+
+    /// Counts the segments sharing at least one point with `range`, without visiting the rest.
+    /// Segments only touching an endpoint of `range` are not counted.
+    pub fn count_overlapping(&self, range: &Segment<K>) -> usize {
+        self.root.as_ref().map_or(0, |root| root.count_overlapping(range))
+    }
+
+    fn insert_disjoint(&mut self, segment: Segment<K>, value: V) {
+        // if there are spare links, build the node in them
+        let node = if let Some(links) = self.spare.pop() {
+            SegmentMapNode::leaf(segment, value, links)
+        } else { SegmentMapNode::new(segment, value, None, None) };
+        self.root = Some(if let Some(root) = self.root.take() {
+            root.insert_node(node)
+        } else { node });
+        self.len += 1;
+    }
+
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&Segment<K>, &V) -> bool
+    {
+        let entries = mem::take(self).into_iter().filter(|(segment, value)| keep(segment, value)).collect();
+        self.rebuild(entries);
+    }
+
+    /// Removes the entries for which `extract` returns `true`, yielding them in ascending order.
+    ///
+    /// Unlike `retain`, the removed entries are handed back. They are removed immediately, whether
+    /// or not the returned iterator is consumed.
+    pub fn extract_if<F>(&mut self, mut extract: F) -> impl Iterator<Item = (Segment<K>, V)>
+    where
+        F: FnMut(&Segment<K>, &V) -> bool
+    {
+        let (extracted, entries): (Vec<_>, Vec<_>) = mem::take(self).into_iter().partition(|(segment, value)| extract(segment, value));
+        self.rebuild(entries);
+        extracted.into_iter()
+    }
+
+    /// Rebuilds the tree in linear time to the minimum possible height, keeping the same entries.
+    ///
+    /// The tree stays balanced through every mutation, so this is never required; it only
+    /// tightens the height (by at most a constant factor) ahead of a lookup-heavy phase.
+    pub fn rebalance(&mut self) {
+        let entries = mem::take(self).into_iter().collect();
+        self.rebuild(entries);
+    }
+
+    /// Replaces each value with `smooth(left, value, right)`, where `left` and `right` are the values
+    /// of the abutting neighbors (`None` across a gap or at either end).
+    ///
+    /// Every new value is computed from the original values, so changes do not cascade.
+    pub fn smooth<F>(&mut self, smooth: F)
+    where
+        F: Fn(Option<&V>, &V, Option<&V>) -> V
+    {
+        let entries = self.iter().collect::<Vec<_>>();
+        let smoothed = entries.iter().enumerate().map(|(i, &(segment, value))| {
+            let left = i.checked_sub(1).map(|j| entries[j]).filter(|(left, _)| left.upper() == segment.lower()).map(|(_, left)| left);
+            let right = entries.get(i + 1).filter(|(right, _)| segment.upper() == right.lower()).map(|&(_, right)| right);
+            smooth(left, value, right)
+        }).collect::<Vec<_>>();
+        for (value, smoothed) in self.values_mut().zip(smoothed) {
+            *value = smoothed;
         }
     }
+
+    fn rebuild(&mut self, entries: Vec<(Segment<K>, V)>) {
+        self.len = entries.len();
+        self.root = SegmentMapNode::from_sorted(&mut entries.into_iter(), self.len);
+    }
 }
 
 impl<K, V> SegmentMap<K, V> 
@@ -80,181 +513,4393 @@ where
     K: Clone + PartialOrd,
     V: Clone,
 {
+    /// Looks up `segment`, capturing the stored entries it overlaps.
+    pub fn entry(&mut self, segment: Segment<K>) -> Entry<'_, K, V> {
+        let mut conflicts = Vec::new();
+        if let Some(root) = self.root.as_ref() {
+            root.visit_overlapping(&segment, &mut |segment, value| conflicts.push((segment.clone(), value.clone())));
+        }
+        if conflicts.is_empty() {
+            Entry::Vacant(VacantEntry { map: self, segment })
+        } else {
+            Entry::Occupied(OccupiedEntry { map: self, segment, conflicts })
+        }
+    }
+
     pub fn remove(&mut self, segment: &Segment<K>) {
         if let Some(root) = self.root.take() {
-            self.root = root.remove(segment);
+            self.root = root.remove(segment, &mut self.len);
+        }
+    }
+
+    /// Removes `segment` like `remove`, returning the removed portions in ascending order.
+    ///
+    /// Removing an empty segment only deletes an identical empty segment, which is returned if present.
+    pub fn remove_and_collect(&mut self, segment: &Segment<K>) -> Vec<(Segment<K>, V)> {
+        let mut removed = Vec::new();
+        if let Some(root) = self.root.as_ref() {
+            if segment.is_empty() {
+                removed.extend(root.get_exact(segment).map(|value| (segment.clone(), value.clone())));
+            } else {
+                root.clone_overlapping(segment, &mut removed);
+            }
         }
+        self.remove(segment);
+        removed
+    }
+
+    /// Removes `segment` like `remove`, yielding the removed portions in ascending order.
+    pub fn extract(&mut self, segment: &Segment<K>) -> impl Iterator<Item = (Segment<K>, V)> {
+        self.remove_and_collect(segment).into_iter()
     }
 
-    pub fn update<F>(&mut self, segment: &Segment<K>, value: F) 
+    /// Replaces the value of every piece of `segment` with `value` of its current value (`None` for
+    /// uncovered pieces), removing pieces for which `value` returns `None`.
+    ///
+    /// Stored segments straddling the bounds of `segment` are split, and `value` is called exactly
+    /// once per piece, in ascending order.
+    pub fn update<F>(&mut self, segment: &Segment<K>, mut value: F) 
     where
-        F: Fn(Option<V>) -> Option<V> + Clone
+        F: FnMut(Option<V>) -> Option<V>
     {
         if let Some(root) = self.root.take() {
-            self.root = root.update(segment, value);
+            self.root = root.update(segment, &mut value, &mut self.len);
         } else if let Some(value) = value(None) {
-            self.insert(segment.clone(), value);
+            self.insert_disjoint(segment.clone(), value);
         }
     }
 
-    pub fn update_entry<F>(&mut self, segment: &Segment<K>, value: F)
+    /// Like `update`, but `value` also receives the piece it is updating.
+    pub fn update_entry<F>(&mut self, segment: &Segment<K>, mut value: F)
     where
-        F: Fn(&Segment<K>, Option<V>) -> Option<V> + Clone
+        F: FnMut(&Segment<K>, Option<V>) -> Option<V>
     {
         if let Some(root) = self.root.take() {
-            self.root = root.update_entry(segment, value);
+            self.root = root.update_entry(segment, &mut value, &mut self.len);
         } else if let Some(value) = value(segment, None) {
-            self.insert(segment.clone(), value);
+            self.insert_disjoint(segment.clone(), value);
         }
     }
-}
-
-pub struct Segments<'a, K, V> {
-    inner: Iter<'a, K, V>
-}
-
-impl<'a, K, V> Iterator for Segments<'a, K, V> {
-    type Item = &'a Segment<K>;
 
-    fn next(&mut self) -> Option<&'a Segment<K>> {
-        self.inner.next().map(|(segment, _)| segment)
+    /// Maps `segment` to `value`, overwriting whatever overlaps it.
+    ///
+    /// Overlapped segments are split and only their portions outside `segment` are kept. Inserting
+    /// an empty segment replaces an identical one.
+    ///
+    /// Splitting clones the keys and values, so this requires `K: Clone` and `V: Clone`, which
+    /// `insert` did not while it panicked on overlap. For a `V` that isn't `Clone`, `try_insert`
+    /// needs only `K: Clone` and hands the value back rather than overwriting.
+    pub fn insert(&mut self, segment: Segment<K>, value: V) {
+        // empty segments strictly within segment are overwritten too
+        let mut empty = Vec::new();
+        if let Some(root) = self.root.as_ref() {
+            root.clone_empty_within(&segment, &mut empty);
+        }
+        for empty in empty {
+            self.remove(&empty);
+        }
+        self.remove(&segment);
+        self.insert_disjoint(segment, value);
     }
-}
-
-pub struct Values<'a, K, V> {
-    inner: Iter<'a, K, V>
-}
-
-impl<'a, K, V> Iterator for Values<'a, K, V> {
-    type Item = &'a V;
 
-    fn next(&mut self) -> Option<&'a V> {
-        self.inner.next().map(|(_, value)| value)
+    /// Inserts like `insert`, returning the displaced entries (or the displaced portions of them) in
+    /// ascending order.
+    pub fn insert_overwrite(&mut self, segment: Segment<K>, value: V) -> Vec<(Segment<K>, V)> {
+        let mut empty = Vec::new();
+        if let Some(root) = self.root.as_ref() {
+            root.clone_empty_within(&segment, &mut empty);
+        }
+        let mut displaced = self.remove_and_collect(&segment);
+        // empty segments strictly within segment go between the pieces around them
+        for empty in empty {
+            let at = displaced.iter().position(|(other, _)| other.lower() >= empty.lower()).unwrap_or(displaced.len());
+            let removed = self.remove_and_collect(&empty);
+            displaced.splice(at..at, removed);
+        }
+        self.insert_disjoint(segment, value);
+        displaced
     }
-}
-
-pub struct ValuesMut<'a, K, V> {
-    inner: IterMut<'a, K, V>
-}
 
-impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
-    type Item = &'a mut V;
+    /// Inserts a batch of segments with the same result as inserting them one at a time, but in a
+    /// single merge pass over the existing contents rather than one descent (and possible split)
+    /// per segment.
+    ///
+    /// The segments must be in ascending order and must not overlap one another. In debug builds,
+    /// panics if they do.
+    pub fn insert_many<I>(&mut self, sorted: I)
+    where
+        I: IntoIterator<Item = (Segment<K>, V)>
+    {
+        let inserted = sorted.into_iter().collect::<Vec<_>>();
+        debug_assert!(is_sorted_disjoint(&inserted), "segments must be sorted and must not overlap");
+        let mut kept = Vec::new();
+        let mut first = 0;
+        for (segment, value) in mem::take(self) {
+            // skip inserted segments that end before this one (an identical empty one still applies)
+            while inserted.get(first).is_some_and(|(next, _)| (next.upper() <= segment.lower()) && (next != &segment)) {
+                first += 1;
+            }
+            let mut pieces = Vec::new();
+            // if empty, keep it unless it is replaced or strictly within an inserted segment
+            if segment.is_empty() {
+                let overwritten = inserted[first..].iter()
+                    .take_while(|(next, _)| next.lower() <= segment.lower())
+                    .any(|(next, _)| (next == &segment) || ((next.lower() < segment.lower()) && (segment.upper() < next.upper())));
+                if !overwritten {
+                    pieces.push(segment);
+                }
+            // otherwise, keep the portions between the inserted segments within it (even an empty one
+            // splits it)
+            } else {
+                let mut covered = segment.lower().clone();
+                for (next, _) in inserted[first..].iter().take_while(|(next, _)| next.lower() < segment.upper()) {
+                    if &covered < next.upper() {
+                        if &covered < next.lower() {
+                            pieces.push(Segment::new(covered, next.lower().clone()));
+                        }
+                        covered = next.upper().clone();
+                    }
+                }
+                if &covered < segment.upper() {
+                    pieces.push(Segment::new(covered, segment.upper().clone()));
+                }
+            }
+            // every piece but the last gets a copy of the value
+            if let Some(last) = pieces.pop() {
+                kept.extend(pieces.into_iter().map(|piece| (piece, value.clone())));
+                kept.push((last, value));
+            }
+        }
+        // the kept pieces and the inserted segments are disjoint, so merging them keeps order
+        let mut merged = Vec::with_capacity(kept.len() + inserted.len());
+        let mut kept = kept.into_iter().peekable();
+        let mut inserted = inserted.into_iter().peekable();
+        while let Some((segment, _)) = kept.peek() {
+            if inserted.peek().is_some_and(|(next, _)| next < segment) {
+                merged.extend(inserted.next());
+            } else {
+                merged.extend(kept.next());
+            }
+        }
+        merged.extend(inserted);
+        self.rebuild(merged);
+    }
 
-    fn next(&mut self) -> Option<&'a mut V> {
-        self.inner.next().map(|(_, value)| value)
+    /// Moves everything at or after `key` into the returned map, like `BTreeMap::split_off`.
+    ///
+    /// A segment straddling `key` is split, with its value cloned into both pieces. An empty segment
+    /// at `key` moves.
+    pub fn split_off(&mut self, key: &K) -> SegmentMap<K, V> {
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+        for (segment, value) in mem::take(self) {
+            // if segment starts at or after key, it moves
+            if segment.lower() >= key {
+                right.push((segment, value));
+            // if segment ends at or before key, it stays
+            } else if segment.upper() <= key {
+                left.push((segment, value));
+            // otherwise, segment straddles key, split it
+            } else {
+                left.push((Segment::new(segment.lower().clone(), key.clone()), value.clone()));
+                right.push((Segment::new(key.clone(), segment.upper().clone()), value));
+            }
+        }
+        self.rebuild(left);
+        SegmentMap::from_sorted(right)
     }
-}
 
-pub struct Iter<'a, K, V> {
-    current: Option<&'a SegmentMapNode<K, V>>,
-    stack: Vec<(&'a Segment<K>, &'a V, Option<&'a SegmentMapNode<K, V>>)>,
-}
+    /// Moves everything from `other` into the map, leaving `other` empty.
+    ///
+    /// Each segment of `other` is inserted as by `insert`, so where the maps overlap, `other` wins.
+    pub fn append(&mut self, other: &mut SegmentMap<K, V>) {
+        // if the map is empty, just take other
+        if self.is_empty() {
+            mem::swap(self, other);
+        } else {
+            for (segment, value) in mem::take(other) {
+                self.insert(segment, value);
+            }
+        }
+    }
 
-impl<'a, K, V> Iterator for Iter<'a, K, V> {
-    type Item = (&'a Segment<K>, &'a V);
+    pub fn set_range(&mut self, range: &Segment<K>, value: V) {
+        self.update(range, |_| Some(value.clone()));
+    }
 
-    fn next(&mut self) -> Option<(&'a Segment<K>, &'a V)> {
-        while let Some(current) = self.current.take() {
-            self.stack.push((&current.segment, &current.value, (*current.right).as_ref()));
-            self.current = (*current.left).as_ref();
+    /// Overlays `other` onto the map, keeping whichever value has the greater `priority` where the
+    /// two overlap (`other` wins ties).
+    pub fn overlay_by_priority<P>(&mut self, other: &SegmentMap<K, V>, priority: P)
+    where
+        P: Fn(&V) -> i32
+    {
+        for (segment, value) in other.iter() {
+            self.update(segment, |existing| match existing {
+                Some(existing) if priority(&existing) > priority(value) => Some(existing),
+                _ => Some(value.clone()),
+            });
         }
-        if let Some((segment, value, right)) = self.stack.pop() {
-            self.current = right;
-            Some((segment, value))
-        } else { None }
     }
-}
 
-pub struct IterMut<'a, K, V> {
-    current: Option<&'a mut SegmentMapNode<K, V>>,
-    stack: Vec<(&'a Segment<K>, &'a mut V, Option<&'a mut SegmentMapNode<K, V>>)>,
-}
+    /// Runs `edit` against the map, restoring the map exactly as it was if `edit` returns `Err`.
+    pub fn transaction<R, E, F>(&mut self, edit: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut SegmentMap<K, V>) -> Result<R, E>
+    {
+        let snapshot = self.clone();
+        let result = edit(self);
+        if result.is_err() {
+            *self = snapshot;
+        }
+        result
+    }
 
-impl<'a, K, V> Iterator for IterMut<'a, K, V> {
-    type Item = (&'a Segment<K>, &'a mut V);
+    /// Encodes the map as `(boundary, value)` pairs, each marking where a value begins.
+    ///
+    /// Ends are implicit (the next boundary, or the end of the span for the last pair), so gaps
+    /// cannot be represented: a gap is absorbed into the run before it. Only gapless maps round-trip.
+    pub fn to_rle(&self) -> Vec<(K, V)> {
+        self.iter().map(|(segment, value)| (segment.lower().clone(), value.clone())).collect()
+    }
 
-    fn next(&mut self) -> Option<(&'a Segment<K>, &'a mut V)> {
-        while let Some(current) = self.current.take() {
-            self.stack.push((&current.segment, &mut current.value, (*current.right).as_mut()));
-            self.current = (*current.left).as_mut();
+    fn move_range<F>(&mut self, range: &Segment<K>, mut shift: F)
+    where
+        F: FnMut(K) -> K
+    {
+        let mut moved = Vec::new();
+        if let Some(root) = self.root.as_ref() {
+            root.clone_overlapping(range, &mut moved);
+        }
+        self.remove(range);
+        for (segment, value) in moved {
+            let segment = Segment::new(shift(segment.lower().clone()), shift(segment.upper().clone()));
+            self.set_range(&segment, value);
         }
-        if let Some((segment, value, right)) = self.stack.pop() {
-            self.current = right;
-            Some((segment, value))
-        } else { None }
     }
 }
 
-impl<K, V> Extend<(Segment<K>, V)> for SegmentMap<K, V> 
+impl<K, V> SegmentMap<K, V>
 where
     K: Clone + PartialOrd,
-    V: Clone,
+    V: Clone + PartialEq,
 {
-    fn extend<I>(&mut self, iter: I) 
+    /// Inserts like `insert`, then merges the result with abutting neighbors holding an equal value.
+    pub fn insert_coalesce(&mut self, segment: Segment<K>, value: V) {
+        self.insert(segment.clone(), value.clone());
+        if !segment.is_empty() {
+            if let Some(root) = self.root.as_ref() {
+                // extend over abutting neighbors with equal values
+                let lower = root.get_ending_at(segment.lower()).filter(|(_, other)| **other == value).map(|(other, _)| other.lower().clone());
+                let upper = root.get_starting_at(segment.upper()).filter(|(_, other)| **other == value).map(|(other, _)| other.upper().clone());
+                if lower.is_some() || upper.is_some() {
+                    let lower = lower.unwrap_or_else(|| segment.lower().clone());
+                    let upper = upper.unwrap_or_else(|| segment.upper().clone());
+                    self.insert(Segment::new(lower, upper), value);
+                }
+            }
+        }
+    }
+
+    /// Extends like `Extend`, but inserts each pair with `insert_coalesce`, so runs of equal values
+    /// end up as single segments.
+    pub fn extend_coalescing<I>(&mut self, iter: I)
     where
         I: IntoIterator<Item = (Segment<K>, V)>
     {
         for (segment, value) in iter {
-            self.insert(segment, value);
+            self.insert_coalesce(segment, value);
         }
     }
 }
 
-impl<K, V> IntoIterator for SegmentMap<K, V> {
-    type Item = (Segment<K>, V);
-    type IntoIter = IntoIter<K, V>;
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd + Add<Output = K>,
+    V: Clone,
+{
+    /// Moves the contents of `range` by `delta`, overwriting whatever is at the destination.
+    ///
+    /// Segments straddling the bounds of `range` are split and only the portion inside is moved.
+    /// The new bounds are computed with `K`'s `Add`, so overflow behaves as it does there (e.g.
+    /// panics in debug builds for primitive integers).
+    pub fn shift_range(&mut self, range: &Segment<K>, delta: K) {
+        self.move_range(range, |key| key + delta.clone());
+    }
+}
 
-    fn into_iter(self) -> IntoIter<K, V> {
-        IntoIter {
-            current: self.root,
-            stack: Vec::new(),
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd + Measure,
+    K::Width: Sum,
+{
+    /// Sums the lengths of all segments, e.g. to measure how much of a range is occupied.
+    pub fn total_covered(&self) -> K::Width {
+        self.segments().map(Segment::length).sum()
+    }
+}
+
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd + Measure,
+{
+    /// Iterates over the entries overlapping the window `[key - radius, key + radius)`, as `range`
+    /// would. The window is clipped to the range of `K` rather than overflowing, and a window whose
+    /// bounds come out inverted (e.g. for a negative `radius`) is empty.
+    pub fn around(&self, key: &K, radius: K::Width) -> impl Iterator<Item = (&Segment<K>, &V)> {
+        let (lower, upper) = (key.sub_width_saturating(&radius), key.add_width_saturating(&radius));
+        let mut entries = Vec::new();
+        // if the window is a point, only the segment containing it matches
+        if lower == upper {
+            entries.extend(self.get_entry(&lower));
+        // otherwise, collect the segments overlapping it (`range` would borrow the local window)
+        } else if lower < upper {
+            if let Some(root) = self.root.as_ref() {
+                root.visit_overlapping(&Segment::new(lower, upper), &mut |segment, value| entries.push((segment, value)));
+            }
         }
+        entries.into_iter()
     }
 }
 
-pub struct IntoIter<K, V> {
-    current: Option<SegmentMapNode<K, V>>,
-    stack: Vec<(Segment<K>, V, Option<SegmentMapNode<K, V>>)>,
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd + Add<Output = K> + Sub<Output = K>,
+    V: Clone,
+{
+    /// Shifts everything at or after `at` right by the length of `segment`, then inserts `segment`.
+    ///
+    /// A segment straddling `at` is split and only its right portion is shifted. The tail is always
+    /// shifted by the length of `segment`, but `segment` itself is inserted at its own bounds
+    /// (overwriting anything there), so if it does not start at `at` it may leave a gap or overwrite
+    /// some of the shifted content.
+    pub fn ripple_insert(&mut self, at: K, segment: Segment<K>, value: V) {
+        if let Some(upper) = self.span().map(|span| K::clone(span.upper())) {
+            if at < upper {
+                let delta = segment.upper().clone() - segment.lower().clone();
+                self.shift_range(&Segment::new(at, upper), delta);
+            }
+        }
+        self.set_range(&segment, value);
+    }
+
+    /// Removes `range` and shifts everything after it left by the length of `range`.
+    ///
+    /// Segments straddling either bound of `range` are split. The portion of a segment overlapping
+    /// the end of `range` that lies after it is shifted along with the rest of the tail, so it ends
+    /// up abutting whatever remains before `range`.
+    pub fn ripple_delete(&mut self, range: &Segment<K>) {
+        self.remove(range);
+        if let Some(upper) = self.span().map(|span| K::clone(span.upper())) {
+            if range.upper() < &upper {
+                let delta = range.upper().clone() - range.lower().clone();
+                self.move_range(&Segment::new(range.upper().clone(), upper), |key| key - delta.clone());
+            }
+        }
+    }
 }
 
-impl<K, V> Iterator for IntoIter<K, V> {
-    type Item = (Segment<K>, V);
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd
+{
+    /// Maps `segment` to `value` only if it does not overlap anything already stored, leaving the map
+    /// unchanged and handing `value` back otherwise.
+    pub fn try_insert(&mut self, segment: Segment<K>, value: V) -> Result<(), OccupiedError<K, V>> {
+        let mut conflicts = Vec::new();
+        if let Some(root) = self.root.as_ref() {
+            root.clone_conflicting(&segment, &mut conflicts);
+        }
+        if conflicts.is_empty() {
+            self.insert_disjoint(segment, value);
+            Ok(())
+        } else {
+            Err(OccupiedError { segment, value, conflicts })
+        }
+    }
 
-    fn next(&mut self) -> Option<(Segment<K>, V)> {
-        while let Some(current) = self.current.take() {
-            self.stack.push((current.segment, current.value, *current.right));
-            self.current = *current.left;
+    /// Removes `segment` like `remove`, without requiring `V: Clone`.
+    ///
+    /// `split` is only called when `segment` falls strictly inside a stored segment, leaving a piece
+    /// on either side; it produces the value for the left piece from that of the right.
+    pub fn remove_with<F>(&mut self, segment: &Segment<K>, split: F)
+    where
+        F: Fn(&V) -> V
+    {
+        if let Some(root) = self.root.take() {
+            self.root = root.remove_with(segment, &split, &mut self.len);
         }
-        if let Some((segment, value, right)) = self.stack.pop() {
-            self.current = right;
-            Some((segment, value))
-        } else { None }
+    }
+
+    /// Combines the map with `other` into a new map covering everything either covers.
+    ///
+    /// `combine` is called for each maximal piece between the boundaries of both maps, with the
+    /// value from each map covering it. Empty segments cover nothing, so they are dropped.
+    pub fn union_with<F>(&self, other: &SegmentMap<K, V>, combine: F) -> SegmentMap<K, V>
+    where
+        F: Fn(Option<&V>, Option<&V>) -> V
+    {
+        let mut entries = Vec::new();
+        self.merge_join(other, |segment, left, right| entries.push((segment, combine(left, right))));
+        SegmentMap::from_sorted(entries)
+    }
+
+    /// Combines the map with `other` into a new map covering only what both cover.
+    ///
+    /// `combine` is called for each maximal piece between the boundaries of both maps that both
+    /// cover, with the value from each.
+    pub fn intersect_with<F>(&self, other: &SegmentMap<K, V>, combine: F) -> SegmentMap<K, V>
+    where
+        F: Fn(&V, &V) -> V
+    {
+        let mut entries = Vec::new();
+        self.merge_join(other, |segment, left, right| {
+            if let (Some(left), Some(right)) = (left, right) {
+                entries.push((segment, combine(left, right)));
+            }
+        });
+        SegmentMap::from_sorted(entries)
+    }
+
+    // visits, in ascending order, each maximal piece covered by either map along with the value
+    // from each map covering it
+    fn merge_join<'a, W, F>(&'a self, other: &'a SegmentMap<K, W>, mut visit: F)
+    where
+        F: FnMut(Segment<K>, Option<&'a V>, Option<&'a W>)
+    {
+        let left = self.iter().filter(|(segment, _)| !segment.is_empty()).collect::<Vec<_>>();
+        let right = other.iter().filter(|(segment, _)| !segment.is_empty()).collect::<Vec<_>>();
+        let (mut i, mut j) = (0, 0);
+        // everything before at has been visited
+        let mut at: Option<&'a K> = None;
+        while (i < left.len()) || (j < right.len()) {
+            let (l, r) = (left.get(i), right.get(j));
+            let lower = |segment: &'a Segment<K>| at.filter(|at| *at > segment.lower()).unwrap_or_else(|| segment.lower());
+            // start at the earliest remaining lower bound
+            let start = match (l, r) {
+                (Some((l, _)), Some((r, _))) => if lower(r) < lower(l) { lower(r) } else { lower(l) },
+                (Some((segment, _)), None) | (None, Some((segment, _))) => lower(segment),
+                (None, None) => unreachable!(),
+            };
+            let l_covers = l.filter(|(segment, _)| segment.lower() <= start);
+            let r_covers = r.filter(|(segment, _)| segment.lower() <= start);
+            // end at the nearest boundary after start
+            let l_end = l.map(|(segment, _)| if l_covers.is_some() { segment.upper() } else { segment.lower() });
+            let r_end = r.map(|(segment, _)| if r_covers.is_some() { segment.upper() } else { segment.lower() });
+            let end = match (l_end, r_end) {
+                (Some(l_end), Some(r_end)) => if r_end < l_end { r_end } else { l_end },
+                (Some(end), None) | (None, Some(end)) => end,
+                (None, None) => unreachable!(),
+            };
+            visit(Segment::new(start.clone(), end.clone()), l_covers.map(|(_, value)| *value), r_covers.map(|(_, value)| *value));
+            // if a segment has been fully visited, move past it
+            if l.is_some_and(|(segment, _)| segment.upper() <= end) {
+                i += 1;
+            }
+            if r.is_some_and(|(segment, _)| segment.upper() <= end) {
+                j += 1;
+            }
+            at = Some(end);
+        }
+    }
+
+    pub fn from_rle(pairs: Vec<(K, V)>, end: K) -> SegmentMap<K, V> {
+        debug_assert!(pairs.windows(2).all(|pair| pair[0].0 < pair[1].0), "boundaries must be strictly increasing");
+        debug_assert!(pairs.last().is_none_or(|(boundary, _)| boundary < &end), "boundaries must be below end");
+        let mut entries = Vec::with_capacity(pairs.len());
+        let mut pairs = pairs.into_iter().peekable();
+        while let Some((lower, value)) = pairs.next() {
+            // each run ends where the next begins, the last ends at end
+            let upper = pairs.peek().map_or_else(|| end.clone(), |(upper, _)| upper.clone());
+            entries.push((Segment::new(lower, upper), value));
+        }
+        let mut segment_map = SegmentMap::new();
+        segment_map.rebuild(entries);
+        segment_map
+    }
+
+    /// Same as `span`, but with owned endpoints that outlive the borrow of the map.
+    pub fn span_cloned(&self) -> Option<Segment<K>> {
+        self.span().map(|span| Segment::new(K::clone(span.lower()), K::clone(span.upper())))
+    }
+
+    /// Iterates over the covered segments and the gaps between them, in order.
+    ///
+    /// As with `gaps`, empty segments cover nothing, so they are skipped and never split a gap.
+    pub fn iter_spans(&self) -> Spans<'_, K, V> {
+        Spans {
+            inner: self.iter(),
+            covered: None,
+            pending: None,
+        }
+    }
+
+    /// Iterates over the maximal uncovered intervals within the span of the map.
+    ///
+    /// Empty segments cover nothing, so they never split a gap.
+    pub fn gaps(&self) -> Gaps<'_, K, V> {
+        Gaps {
+            inner: self.iter(),
+            covered: None,
+        }
+    }
+
+    /// Iterates over the maximal intervals within `bounds` not covered by any segment.
+    pub fn gaps_within<'a>(&'a self, bounds: &'a Segment<K>) -> GapsWithin<'a, K, V> {
+        GapsWithin {
+            inner: self.range(bounds),
+            upper: bounds.upper(),
+            covered: Some(bounds.lower().clone()).filter(|_| !bounds.is_empty()),
+        }
+    }
+
+    /// Returns `true` if every point of `segment` is covered, possibly by several contiguous
+    /// segments. An empty `segment` is trivially covered.
+    pub fn contains_segment(&self, segment: &Segment<K>) -> bool {
+        self.gaps_within(segment).next().is_none()
+    }
+
+    pub fn gaps_with_neighbors(&self) -> GapsWithNeighbors<'_, K, V> {
+        GapsWithNeighbors {
+            inner: self.iter(),
+            previous: None,
+        }
+    }
+
+    /// Greedily merges adjacent segments until at most `max_segments` remain.
+    ///
+    /// Each step merges the pair of neighboring segments whose `merge_cost` is lowest, replacing
+    /// them with a single segment spanning both (including any gap between them) with the merged
+    /// value. Ties are broken in favor of the leftmost pair. A nonempty map is never reduced below
+    /// one segment.
+    pub fn simplify_to<F>(&mut self, max_segments: usize, merge_cost: F)
+    where
+        F: Fn(&V, &V) -> (V, u64)
+    {
+        let mut entries = mem::take(self).into_iter().map(Some).collect::<Vec<_>>();
+        let mut remaining = entries.len();
+        if remaining > max_segments {
+            let mut prev = (0..entries.len()).map(|i| i.checked_sub(1)).collect::<Vec<_>>();
+            let mut next = (0..entries.len()).map(|i| Some(i + 1).filter(|&j| j < entries.len())).collect::<Vec<_>>();
+            let mut versions = vec![0; entries.len()];
+            let mut merged = (0..entries.len()).map(|_| None).collect::<Vec<_>>();
+            let mut heap = BinaryHeap::new();
+            // cost of merging the (live) entries at `i` and `j`
+            let cost = |entries: &[Option<(Segment<K>, V)>], i: usize, j: usize| {
+                let (_, left) = entries[i].as_ref().expect("merge candidate is missing");
+                let (_, right) = entries[j].as_ref().expect("merge candidate is missing");
+                merge_cost(left, right)
+            };
+            for i in 1..entries.len() {
+                let (value, cost) = cost(&entries, i - 1, i);
+                merged[i - 1] = Some(value);
+                heap.push(Reverse((cost, i - 1, i, 0, 0)));
+            }
+            while remaining > max_segments.max(1) {
+                let Reverse((_, i, j, version_i, version_j)) = match heap.pop() {
+                    Some(pair) => pair,
+                    None => break,
+                };
+                // skip pairs invalidated by an earlier merge
+                if (next[i] != Some(j)) || (versions[i] != version_i) || (versions[j] != version_j) {
+                    continue;
+                }
+                let (left, _) = entries[i].take().expect("merged entry is missing");
+                let (right, _) = entries[j].take().expect("merged entry is missing");
+                let value = merged[i].take().expect("merged value is missing");
+                let segment = Segment::new(left.lower().clone(), right.upper().clone());
+                entries[i] = Some((segment, value));
+                versions[i] += 1;
+                // j is absorbed, so pairs starting at it are invalid too
+                versions[j] += 1;
+                next[i] = next[j].take();
+                if let Some(k) = next[i] {
+                    prev[k] = Some(i);
+                }
+                remaining -= 1;
+                // requeue the pairs touching the merged segment
+                if let Some(h) = prev[i] {
+                    let (value, cost) = cost(&entries, h, i);
+                    merged[h] = Some(value);
+                    heap.push(Reverse((cost, h, i, versions[h], versions[i])));
+                }
+                if let Some(k) = next[i] {
+                    let (value, cost) = cost(&entries, i, k);
+                    merged[i] = Some(value);
+                    heap.push(Reverse((cost, i, k, versions[i], versions[k])));
+                }
+            }
+        }
+        self.rebuild(entries.into_iter().flatten().collect());
+    }
+
+    pub fn inspect(&self, range: &Segment<K>) -> RangeInfo<K> {
+        let mut info = RangeInfo {
+            contained: 0,
+            lower_straddle: None,
+            upper_straddle: None,
+            has_gap: false,
+        };
+        if let Some(root) = self.root.as_ref() {
+            info.contained = root.count_enclosed(range);
+            let mut covered = range.lower();
+            root.visit_overlapping(range, &mut |segment, _| {
+                // if segment straddles the lower bound
+                if segment.lower() < range.lower() {
+                    info.lower_straddle = segment.intersection(range).map(|overlap| (segment.clone(), overlap));
+                }
+                // if segment straddles the upper bound
+                if segment.upper() > range.upper() {
+                    info.upper_straddle = segment.intersection(range).map(|overlap| (segment.clone(), overlap));
+                }
+                // if segment doesn't pick up where the last left off, there is a gap
+                if segment.lower() > covered {
+                    info.has_gap = true;
+                }
+                covered = segment.upper();
+            });
+            // if the last segment doesn't reach the upper bound, there is a gap
+            if covered < range.upper() {
+                info.has_gap = true;
+            }
+        } else if !range.is_empty() {
+            info.has_gap = true;
+        }
+        info
     }
 }
 
-#[macro_export]
-macro_rules! segment_map {
-    ($($x:expr => $y:expr),*) => {{
-        #[allow(unused_mut)]
-        let mut temp_segment_map = $crate::SegmentMap::new();
-        $(temp_segment_map.insert($x, $y);)*
-        temp_segment_map
-    }}
+impl<K, V> SegmentMap<K, V>
+where
+    K: PartialOrd + Measure
+{
+    /// Shrinks every segment inward by `margin` on each side.
+    ///
+    /// Segments that become empty or inverted (including by overflow) are dropped, so abutting
+    /// segments end up separated by twice `margin`.
+    pub fn erode(&mut self, margin: K::Width) {
+        let entries = mem::take(self).into_iter().filter_map(|(segment, value)| {
+            let lower = segment.lower().add_width_checked(&margin)?;
+            let upper = segment.upper().sub_width_checked(&margin)?;
+            // if erosion empties the segment, drop it
+            if lower < upper {
+                Some((Segment::new(lower, upper), value))
+            } else { None }
+        }).collect();
+        self.rebuild(entries);
+    }
+
+    pub fn drop_shorter_than(&mut self, min_len: K::Width) {
+        self.retain(|segment, _| segment.width() >= min_len);
+    }
+
+    /// Grows every segment outward by `margin` on each side, merging segments that come to overlap.
+    ///
+    /// Endpoints saturate rather than overflow. Segments that merely touch after growing are kept
+    /// separate. A chain of overlapping segments is merged left to right into a single segment, so
+    /// its value is `combine(combine(a, b), c)` and so on.
+    pub fn dilate<F>(&mut self, margin: K::Width, combine: F)
+    where
+        F: Fn(&V, &V) -> V
+    {
+        let mut entries = Vec::new();
+        let mut pending: Option<(K, K, V)> = None;
+        for (segment, value) in mem::take(self) {
+            let lower = segment.lower().sub_width_saturating(&margin);
+            let upper = segment.upper().add_width_saturating(&margin);
+            pending = Some(match pending.take() {
+                // if grown segment overlaps the pending one, merge them
+                Some((pending_lower, pending_upper, pending_value)) if lower < pending_upper => {
+                    let value = combine(&pending_value, &value);
+                    (pending_lower, if pending_upper < upper { upper } else { pending_upper }, value)
+                },
+                // otherwise, the pending segment is final
+                Some((pending_lower, pending_upper, pending_value)) => {
+                    entries.push((Segment::new(pending_lower, pending_upper), pending_value));
+                    (lower, upper, value)
+                },
+                None => (lower, upper, value),
+            });
+        }
+        if let Some((lower, upper, value)) = pending {
+            entries.push((Segment::new(lower, upper), value));
+        }
+        self.rebuild(entries);
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        Segment,
-        SegmentMap,
-    };
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd + Measure
+{
+    /// Splits the widest segment in two, returning `false` if no segment was split.
+    ///
+    /// `split` receives the widest nonempty segment (the first, if tied) and its value and returns
+    /// the point to split at along with the values for the left and right pieces. The map is left
+    /// unchanged if it holds no nonempty segment or if the split point does not lie strictly inside
+    /// the segment, since either piece would then be empty.
+    pub fn split_largest<F>(&mut self, split: F) -> bool
+    where
+        F: Fn(&Segment<K>, &V) -> (K, V, V)
+    {
+        let mut entries = mem::take(self).into_iter().collect::<Vec<_>>();
+        let mut widest: Option<(usize, K::Width)> = None;
+        for (i, (segment, _)) in entries.iter().enumerate() {
+            if segment.is_empty() {
+                continue;
+            }
+            let width = segment.width();
+            if widest.as_ref().is_none_or(|(_, widest)| widest < &width) {
+                widest = Some((i, width));
+            }
+        }
+        let mut was_split = false;
+        if let Some((i, _)) = widest {
+            let (segment, value) = &entries[i];
+            let (at, left, right) = split(segment, value);
+            if (segment.lower() < &at) && (&at < segment.upper()) {
+                let (segment, _) = entries.remove(i);
+                entries.splice(i..i, vec![
+                    (Segment::new(segment.lower().clone(), at.clone()), left),
+                    (Segment::new(at, segment.upper().clone()), right),
+                ]);
+                was_split = true;
+            }
+        }
+        self.rebuild(entries);
+        was_split
+    }
 
-    #[test]
-    fn test_insert_multiple_empty() {
-        let mut segment_map = SegmentMap::new();
-        segment_map.insert(Segment::new(0, 1), 0);
-        segment_map.insert(Segment::new(1, 1), 1);
-        assert!(std::panic::catch_unwind(move || segment_map.insert(Segment::new(1, 1), 2)).is_err());
+    /// Merges the narrowest segment with its narrower abutting neighbor, returning `false` if no two
+    /// segments abut.
+    ///
+    /// Only segments with an abutting (nonempty) neighbor are candidates. Ties are broken in favor
+    /// of the leftmost candidate and then the left neighbor. The merged value is `combine(left, right)`.
+    pub fn merge_smallest<F>(&mut self, combine: F) -> bool
+    where
+        F: Fn(&V, &V) -> V
+    {
+        let mut entries = mem::take(self).into_iter().collect::<Vec<_>>();
+        let widths = entries.iter().map(|(segment, _)| segment.width()).collect::<Vec<_>>();
+        // whether the entry at `i` abuts the one after it
+        let abuts = |i: usize| {
+            entries.get(i + 1).is_some_and(|(right, _)| {
+                let (left, _) = &entries[i];
+                !left.is_empty() && !right.is_empty() && (left.upper() == right.lower())
+            })
+        };
+        let mut smallest: Option<(usize, &K::Width)> = None;
+        for (i, width) in widths.iter().enumerate() {
+            // pick the narrower neighbor, preferring the left
+            let pair = match (i.checked_sub(1).filter(|&j| abuts(j)), Some(i).filter(|&j| abuts(j))) {
+                (Some(left), Some(right)) => Some(if widths[left] <= widths[i + 1] { left } else { right }),
+                (left, right) => left.or(right),
+            };
+            if let Some(pair) = pair {
+                if smallest.is_none_or(|(_, smallest)| width < smallest) {
+                    smallest = Some((pair, width));
+                }
+            }
+        }
+        let merged = smallest.map(|(i, _)| i);
+        if let Some(i) = merged {
+            let (right_segment, right_value) = entries.remove(i + 1);
+            let (left_segment, left_value) = entries.remove(i);
+            let segment = Segment::new(left_segment.lower().clone(), right_segment.upper().clone());
+            entries.insert(i, (segment, combine(&left_value, &right_value)));
+        }
+        self.rebuild(entries);
+        merged.is_some()
+    }
+}
+
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd + Measure,
+    V: Clone,
+{
+    /// Fills every gap between segments no wider than `max_gap` with `fill`.
+    ///
+    /// Filled gaps are not coalesced with their neighbors, even if they hold an equal value.
+    pub fn fill_small_gaps(&mut self, max_gap: K::Width, fill: V) {
+        let mut entries: Vec<(Segment<K>, V)> = Vec::new();
+        for (segment, value) in mem::take(self) {
+            if let Some((last_segment, _)) = entries.last() {
+                // if the gap is small enough, fill it
+                if (last_segment.upper() < segment.lower()) && (last_segment.upper().width_to(segment.lower()) <= max_gap) {
+                    entries.push((Segment::new(last_segment.upper().clone(), segment.lower().clone()), fill.clone()));
+                }
+            }
+            entries.push((segment, value));
+        }
+        self.rebuild(entries);
+    }
+}
+
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd + Measure,
+    V: PartialEq,
+{
+    /// Merges equal-valued neighbors separated by a gap no wider than `max_gap`, filling the gap.
+    ///
+    /// Abutting equal-valued segments (a gap of zero) are merged as well.
+    pub fn bridge_equal(&mut self, max_gap: K::Width) {
+        let mut entries: Vec<(Segment<K>, V)> = Vec::new();
+        for (segment, value) in mem::take(self) {
+            if let Some((last_segment, last_value)) = entries.last_mut() {
+                // if equal values are close enough, bridge them
+                if (*last_value == value) && (last_segment.upper() <= segment.lower()) && (last_segment.upper().width_to(segment.lower()) <= max_gap) {
+                    *last_segment = Segment::new(last_segment.lower().clone(), segment.upper().clone());
+                    continue;
+                }
+            }
+            entries.push((segment, value));
+        }
+        self.rebuild(entries);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> SegmentMap<K, V>
+where
+    K: PartialOrd,
+    V: Eq + Hash,
+{
+    pub fn retain_values_in(&mut self, allowed: &HashSet<V>) {
+        self.retain(|_, value| allowed.contains(value));
+    }
+
+    pub fn remove_values_in(&mut self, removed: &HashSet<V>) {
+        self.retain(|_, value| !removed.contains(value));
+    }
+}
+
+impl<K, V> SegmentMap<K, V>
+where
+    K: PartialOrd,
+    V: PartialEq,
+{
+    /// Returns the boundaries where abutting segments hold differing values.
+    ///
+    /// Gaps are not transitions, and empty segments are skipped.
+    pub fn transitions(&self) -> Transitions<'_, K, V> {
+        Transitions {
+            inner: self.iter(),
+            previous: None,
+        }
+    }
+
+    pub fn transition_count(&self) -> usize {
+        self.transitions().count()
+    }
+}
+
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + PartialOrd,
+    V: PartialEq,
+{
+    pub fn into_coalesced(self) -> IntoCoalesced<K, V> {
+        IntoCoalesced {
+            inner: self.into_iter(),
+            pending: None,
+        }
+    }
+
+    /// Like `into_coalesced`, but borrowing the map, which is left as it is.
+    pub fn iter_coalesced(&self) -> IterCoalesced<'_, K, V> {
+        IterCoalesced {
+            inner: self.iter(),
+            pending: None,
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<K, V> SegmentMap<K, V>
+where
+    K: Clone + Ord + Send,
+    V: Clone + Send,
+{
+    /// Inserts many pairs at once with the same result as `extend`, so later pairs overwrite both
+    /// earlier ones and the existing entries they overlap.
+    ///
+    /// The pairs are sorted in parallel, and if they don't overlap one another, merged into the map
+    /// in a single pass. Otherwise, they are inserted one at a time, in their original order.
+    pub fn par_extend<I>(&mut self, pairs: I)
+    where
+        I: IntoParallelIterator<Item = (Segment<K>, V)>
+    {
+        // remember the original order, in case it decides which pair wins
+        let mut incoming = pairs.into_par_iter().collect::<Vec<_>>().into_iter().enumerate().collect::<Vec<_>>();
+        incoming.par_sort_unstable_by(|(_, (a, _)), (_, (b, _))| a.cmp(b));
+        // if no two pairs overlap, their order doesn't matter
+        if incoming.windows(2).all(|pair| ((pair[0].1).0.upper() <= (pair[1].1).0.lower()) && ((pair[0].1).0 != (pair[1].1).0)) {
+            self.insert_many(incoming.into_iter().map(|(_, pair)| pair));
+        } else {
+            // put each pair back in its original place rather than sorting again
+            let mut pairs = (0..incoming.len()).map(|_| None).collect::<Vec<_>>();
+            for (i, pair) in incoming {
+                pairs[i] = Some(pair);
+            }
+            self.extend(pairs.into_iter().flatten());
+        }
+    }
+}
+
+impl<K, V> SegmentMap<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    /// Renders the shape of the tree for debugging, one level per line with `/` and `\\` leading
+    /// to the children, and every segment in its in-order column.
+    pub fn pretty_tree(&self) -> String {
+        let mut rows = Vec::new();
+        if let Some(root) = self.root.as_ref() {
+            root.draw(0, &mut 0, &mut rows);
+        }
+        rows.iter().map(|row| format!("{}\n", row.iter().collect::<String>())).collect()
+    }
+}
+
+#[cfg(feature = "testing")]
+impl<K, V> SegmentMap<K, V>
+where
+    K: fmt::Debug + PartialOrd
+{
+    /// Verifies the ordering and disjointness of the stored segments, the cached length and the
+    /// balance of the tree, describing the first violation.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut previous: Option<&Segment<K>> = None;
+        for segment in self.segments() {
+            if segment.lower() > segment.upper() {
+                return Err(format!("segment {:?} has its lower bound above its upper bound", segment));
+            }
+            if let Some(previous) = previous {
+                // in-order traversal must yield sorted, disjoint segments
+                if (previous.upper() > segment.lower()) || (previous == segment) {
+                    return Err(format!("segment {:?} is out of order with or overlaps segment {:?}", previous, segment));
+                }
+            }
+            previous = Some(segment);
+        }
+        if self.segments().count() != self.len {
+            return Err(format!("cached length {} does not match {} stored segments", self.len, self.segments().count()));
+        }
+        if self.root.as_ref().is_some_and(|root| root.checked_height().is_none()) {
+            return Err("tree is unbalanced or has a stale height".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct OccupiedError<K, V> {
+    segment: Segment<K>,
+    value: V,
+    conflicts: Vec<Segment<K>>,
+}
+
+impl<K, V> OccupiedError<K, V> {
+    pub fn segment(&self) -> &Segment<K> {
+        &self.segment
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    pub fn conflicts(&self) -> &[Segment<K>] {
+        &self.conflicts
+    }
+
+    pub fn into_value(self) -> V {
+        self.value
+    }
+}
+
+impl<K, V> fmt::Display for OccupiedError<K, V>
+where
+    K: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "segment {:?} overlaps {} existing segment(s)", self.segment, self.conflicts.len())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> Error for OccupiedError<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RangeInfo<K> {
+    contained: usize,
+    lower_straddle: Option<(Segment<K>, Segment<K>)>,
+    upper_straddle: Option<(Segment<K>, Segment<K>)>,
+    has_gap: bool,
+}
+
+impl<K> RangeInfo<K> {
+    pub fn contained(&self) -> usize {
+        self.contained
+    }
+
+    pub fn lower_straddle(&self) -> Option<&(Segment<K>, Segment<K>)> {
+        self.lower_straddle.as_ref()
+    }
+
+    pub fn upper_straddle(&self) -> Option<&(Segment<K>, Segment<K>)> {
+        self.upper_straddle.as_ref()
+    }
+
+    pub fn has_gap(&self) -> bool {
+        self.has_gap
+    }
+}
+
+pub enum Entry<'a, K, V> {
+    /// The segment overlaps at least one stored segment.
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// The segment overlaps no stored segment.
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+pub struct OccupiedEntry<'a, K, V> {
+    map: &'a mut SegmentMap<K, V>,
+    segment: Segment<K>,
+    conflicts: Vec<(Segment<K>, V)>,
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    pub fn segment(&self) -> &Segment<K> {
+        &self.segment
+    }
+
+    /// The stored entries the segment overlaps as of the lookup, in ascending order.
+    pub fn conflicts(&self) -> &[(Segment<K>, V)] {
+        &self.conflicts
+    }
+}
+
+pub struct VacantEntry<'a, K, V> {
+    map: &'a mut SegmentMap<K, V>,
+    segment: Segment<K>,
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+    pub fn segment(&self) -> &Segment<K> {
+        &self.segment
+    }
+}
+
+impl<'a, K, V> Entry<'a, K, V> {
+    pub fn segment(&self) -> &Segment<K> {
+        match self {
+            Entry::Occupied(entry) => entry.segment(),
+            Entry::Vacant(entry) => entry.segment(),
+        }
+    }
+
+    /// The stored entries the segment overlaps as of the lookup, in ascending order. Empty for a
+    /// vacant entry.
+    pub fn conflicts(&self) -> &[(Segment<K>, V)] {
+        match self {
+            Entry::Occupied(entry) => entry.conflicts(),
+            Entry::Vacant(_) => &[],
+        }
+    }
+
+    fn into_parts(self) -> (&'a mut SegmentMap<K, V>, Segment<K>) {
+        match self {
+            Entry::Occupied(entry) => (entry.map, entry.segment),
+            Entry::Vacant(entry) => (entry.map, entry.segment),
+        }
+    }
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Clone + PartialOrd,
+    V: Clone,
+{
+    /// Maps the portions of the entry's segment not covered by any stored segment to `value`,
+    /// leaving the covered portions untouched.
+    ///
+    /// An empty segment is only inserted if nothing identical to or strictly around it is stored.
+    pub fn or_insert(self, value: V) {
+        self.or_insert_with(|| value);
+    }
+
+    /// Like `or_insert`, but only calls `default` if some portion is uncovered.
+    pub fn or_insert_with<F>(self, default: F)
+    where
+        F: FnOnce() -> V
+    {
+        let (map, segment) = self.into_parts();
+        let gaps = if segment.is_empty() {
+            let mut conflicts = Vec::new();
+            if let Some(root) = map.root.as_ref() {
+                root.clone_conflicting(&segment, &mut conflicts);
+            }
+            if conflicts.is_empty() { vec![segment] } else { Vec::new() }
+        } else {
+            map.gaps_within(&segment).collect::<Vec<_>>()
+        };
+        if !gaps.is_empty() {
+            let value = default();
+            for gap in gaps {
+                map.insert(gap, value.clone());
+            }
+        }
+    }
+
+    /// Calls `modify` on the value of each covered portion of the entry's segment, in ascending
+    /// order. Stored segments straddling the bounds of the entry's segment are split so that only
+    /// the portion within is modified. The returned entry is looked up afresh.
+    pub fn and_modify<F>(self, mut modify: F) -> Entry<'a, K, V>
+    where
+        F: FnMut(&mut V)
+    {
+        let (map, segment) = self.into_parts();
+        for (segment, mut value) in map.remove_and_collect(&segment) {
+            modify(&mut value);
+            map.insert_disjoint(segment, value);
+        }
+        map.entry(segment)
+    }
+}
+
+/// A lookup cursor that remembers its position between queries.
+///
+/// Queries falling in the last hit segment, in the segment after it, or in the gap between them are
+/// answered without descending from the root, which makes monotonic scans O(1) per query. Any other
+/// query falls back to a full descent and repositions the cursor.
+pub struct LocalityCursor<'a, K, V> {
+    root: Option<&'a SegmentMapNode<K, V>>,
+    len: usize,
+    current: Option<(&'a Segment<K>, &'a V)>,
+    next: Option<(&'a Segment<K>, &'a V)>,
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> LocalityCursor<'a, K, V>
+where
+    K: PartialOrd
+{
+    pub fn get(&mut self, key: &K) -> Option<&'a V> {
+        // if key is at or after the current position, try the neighborhood first
+        if self.current.is_none_or(|(segment, _)| segment.lower() <= key) {
+            // if current segment contains key
+            if let Some((segment, value)) = self.current {
+                if segment.contains(key) {
+                    return Some(value);
+                }
+            }
+            match self.next {
+                // if next segment contains key, advance
+                Some((segment, value)) if segment.contains(key) => {
+                    self.current = self.next;
+                    self.next = self.iter.next();
+                    return Some(value);
+                },
+                // if key is before next segment, it falls in the gap
+                Some((segment, _)) if key < segment.lower() => return None,
+                // if there is no next segment, key is past the end
+                None => return None,
+                _ => {},
+            }
+        }
+        self.seek(key)
+    }
+
+    fn seek(&mut self, key: &K) -> Option<&'a V> {
+        // the repositioned iterator is private, so its remaining count only needs to bound what is left
+        let mut frames = VecDeque::new();
+        let mut predecessor = None;
+        let mut node = self.root;
+        while let Some(current) = node {
+            // if current segment contains key, position after it
+            if current.segment.contains(key) {
+                if let Some(right) = (*current.right).as_ref() {
+                    frames.push_front(IterFrame::Node(right));
+                }
+                self.iter = Iter { frames, remaining: self.len };
+                self.current = Some((&current.segment, &current.value));
+                self.next = self.iter.next();
+                return Some(&current.value);
+            // if key is less than current segment, current (then its right) is a successor
+            } else if key < current.segment.lower() {
+                if let Some(right) = (*current.right).as_ref() {
+                    frames.push_front(IterFrame::Node(right));
+                }
+                frames.push_front(IterFrame::Entry(&current.segment, &current.value));
+                node = (*current.left).as_ref();
+            // otherwise, current is a predecessor
+            } else {
+                predecessor = Some((&current.segment, &current.value));
+                node = (*current.right).as_ref();
+            }
+        }
+        // key is not covered, position between predecessor and successor
+        self.iter = Iter { frames, remaining: self.len };
+        self.current = predecessor;
+        self.next = self.iter.next();
+        None
+    }
+}
+
+/// A position between two consecutive entries of a map (or before the first, or after the last),
+/// which can be moved in either direction.
+pub struct Cursor<'a, K, V> {
+    root: Option<&'a SegmentMapNode<K, V>>,
+    prev: Option<(&'a Segment<K>, &'a V)>,
+    next: Option<(&'a Segment<K>, &'a V)>,
+}
+
+impl<'a, K, V> Cursor<'a, K, V>
+where
+    K: PartialOrd
+{
+    // `is_before` must hold for a prefix of the entries, which end up behind the cursor
+    fn new<F>(root: Option<&'a SegmentMapNode<K, V>>, is_before: F) -> Cursor<'a, K, V>
+    where
+        F: Fn(&Segment<K>) -> bool
+    {
+        Cursor {
+            root,
+            prev: root.and_then(|root| root.last_where(&is_before)),
+            next: root.and_then(|root| root.first_where(|segment| !is_before(segment))),
+        }
+    }
+
+    pub fn peek_next(&self) -> Option<(&'a Segment<K>, &'a V)> {
+        self.next
+    }
+
+    pub fn peek_prev(&self) -> Option<(&'a Segment<K>, &'a V)> {
+        self.prev
+    }
+
+    /// Moves the cursor past the next entry and returns it, or returns `None` at the end.
+    pub fn move_next(&mut self) -> Option<(&'a Segment<K>, &'a V)> {
+        let (current, _) = self.next?;
+        self.prev = self.next;
+        self.next = self.root.and_then(|root| root.first_where(|segment| segment > current));
+        self.prev
+    }
+
+    /// Moves the cursor before the previous entry and returns it, or returns `None` at the start.
+    pub fn move_prev(&mut self) -> Option<(&'a Segment<K>, &'a V)> {
+        let (current, _) = self.prev?;
+        self.next = self.prev;
+        self.prev = self.root.and_then(|root| root.last_where(|segment| segment < current));
+        self.next
+    }
+}
+
+impl<K, V> Default for SegmentMap<K, V>
+where
+    K: PartialOrd
+{
+    fn default() -> SegmentMap<K, V> {
+        SegmentMap::new()
+    }
+}
+
+// spare links are only an allocation detail, so they are left out
+impl<K, V> fmt::Debug for SegmentMap<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SegmentMap")
+            .field("root", &self.root)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<K, V> Clone for SegmentMap<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    fn clone(&self) -> SegmentMap<K, V> {
+        SegmentMap {
+            root: self.root.clone(),
+            len: self.len,
+            spare: Vec::new(),
+        }
+    }
+
+    // reuse existing nodes wherever the trees have the same shape, cloning afresh elsewhere
+    fn clone_from(&mut self, source: &SegmentMap<K, V>) {
+        self.root.clone_from(&source.root);
+        self.len = source.len;
+    }
+}
+
+// compare contents in order, since the same contents may be stored in differently shaped trees
+impl<K, V> PartialEq for SegmentMap<K, V>
+where
+    K: PartialEq,
+    V: PartialEq,
+{
+    fn eq(&self, other: &SegmentMap<K, V>) -> bool {
+        (self.len == other.len) && self.iter().eq(other.iter())
+    }
+}
+
+impl<K, V> Eq for SegmentMap<K, V>
+where
+    K: Eq,
+    V: Eq,
+{}
+
+// order lexicographically by contents, to agree with `PartialEq`
+impl<K, V> PartialOrd for SegmentMap<K, V>
+where
+    K: PartialOrd,
+    V: PartialOrd,
+{
+    fn partial_cmp(&self, other: &SegmentMap<K, V>) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<K, V> Ord for SegmentMap<K, V>
+where
+    K: Ord,
+    V: Ord,
+{
+    fn cmp(&self, other: &SegmentMap<K, V>) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+// hash contents in order, to agree with `PartialEq`
+impl<K, V> Hash for SegmentMap<K, V>
+where
+    K: Hash,
+    V: Hash,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len.hash(state);
+        for (segment, value) in self.iter() {
+            segment.hash(state);
+            value.hash(state);
+        }
+    }
+}
+
+impl<K, Q, V> Index<&Q> for SegmentMap<K, V>
+where
+    K: Borrow<Q> + PartialOrd,
+    Q: PartialOrd + ?Sized,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no segment contains key")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V> Serialize for SegmentMap<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // serialized as the in-order sequence of [segment, value] pairs
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// Deserializes from a sequence of `[segment, value]` pairs. Rather than resolving overlaps the way
+/// `insert` would, the pairs must already be sorted and disjoint (as `Serialize` produces them),
+/// otherwise deserialization fails.
+#[cfg(feature = "serde")]
+impl<'de, K, V> Deserialize<'de> for SegmentMap<K, V>
+where
+    K: Deserialize<'de> + PartialOrd,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SegmentMap<K, V>, D::Error> {
+        let entries = Vec::<(Segment<K>, V)>::deserialize(deserializer)?;
+        if is_sorted_disjoint(&entries) {
+            Ok(SegmentMap::from_sorted(entries))
+        } else {
+            Err(de::Error::custom("segments must be sorted and must not overlap"))
+        }
+    }
+}
+
+pub struct Segments<'a, K, V> {
+    inner: Iter<'a, K, V>
+}
+
+impl<'a, K, V> Iterator for Segments<'a, K, V> {
+    type Item = &'a Segment<K>;
+
+    fn next(&mut self) -> Option<&'a Segment<K>> {
+        self.inner.next().map(|(segment, _)| segment)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+pub struct Values<'a, K, V> {
+    inner: Iter<'a, K, V>
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.inner.next().map(|(_, value)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+pub struct ValuesMut<'a, K, V> {
+    inner: IterMut<'a, K, V>
+}
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<&'a mut V> {
+        self.inner.next().map(|(_, value)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for ValuesMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<&'a mut V> {
+        self.inner.next_back().map(|(_, value)| value)
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for ValuesMut<'a, K, V> {}
+
+pub struct Gaps<'a, K, V> {
+    inner: Iter<'a, K, V>,
+    covered: Option<&'a K>,
+}
+
+impl<'a, K, V> Iterator for Gaps<'a, K, V>
+where
+    K: Clone + PartialOrd
+{
+    type Item = Segment<K>;
+
+    fn next(&mut self) -> Option<Segment<K>> {
+        for (segment, _) in self.inner.by_ref().filter(|(segment, _)| !segment.is_empty()) {
+            // if there is a gap between the covered prefix and this segment, yield it
+            if let Some(covered) = self.covered.replace(segment.upper()) {
+                if covered < segment.lower() {
+                    return Some(Segment::new(covered.clone(), segment.lower().clone()));
+                }
+            }
+        }
+        None
+    }
+}
+
+pub struct GapsWithin<'a, K, V> {
+    inner: Range<'a, K, V>,
+    upper: &'a K,
+    covered: Option<K>,
+}
+
+impl<'a, K, V> Iterator for GapsWithin<'a, K, V>
+where
+    K: Clone + PartialOrd
+{
+    type Item = Segment<K>;
+
+    fn next(&mut self) -> Option<Segment<K>> {
+        let mut covered = self.covered.take()?;
+        for (segment, _) in self.inner.by_ref() {
+            // if there is a gap between the covered prefix and this segment, yield it
+            if &covered < segment.lower() {
+                self.covered = Some(segment.upper().clone());
+                return Some(Segment::new(covered, segment.lower().clone()));
+            }
+            covered = segment.upper().clone();
+        }
+        // whatever remains up to the upper bound is uncovered
+        if &covered < self.upper {
+            Some(Segment::new(covered, self.upper.clone()))
+        } else { None }
+    }
+}
+
+pub struct GapsWithNeighbors<'a, K, V> {
+    inner: Iter<'a, K, V>,
+    previous: Option<(&'a Segment<K>, &'a V)>,
+}
+
+impl<'a, K, V> Iterator for GapsWithNeighbors<'a, K, V>
+where
+    K: Clone + PartialOrd
+{
+    type Item = (Segment<K>, &'a V, &'a V);
+
+    fn next(&mut self) -> Option<(Segment<K>, &'a V, &'a V)> {
+        for (segment, value) in self.inner.by_ref() {
+            // if there is a gap between the previous segment and this one, yield it
+            if let Some((previous_segment, previous_value)) = self.previous.replace((segment, value)) {
+                if previous_segment.upper() < segment.lower() {
+                    return Some((Segment::new(previous_segment.upper().clone(), segment.lower().clone()), previous_value, value));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Span<'a, K, V> {
+    Covered(&'a Segment<K>, &'a V),
+    Gap(Segment<K>),
+}
+
+pub struct Transitions<'a, K, V> {
+    inner: Iter<'a, K, V>,
+    previous: Option<(&'a Segment<K>, &'a V)>,
+}
+
+impl<'a, K, V> Iterator for Transitions<'a, K, V>
+where
+    K: PartialOrd,
+    V: PartialEq,
+{
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<&'a K> {
+        for (segment, value) in self.inner.by_ref().filter(|(segment, _)| !segment.is_empty()) {
+            // if the previous segment abuts this one with a different value, yield the boundary
+            if let Some((previous_segment, previous_value)) = self.previous.replace((segment, value)) {
+                if (previous_segment.upper() == segment.lower()) && (previous_value != value) {
+                    return Some(segment.lower());
+                }
+            }
+        }
+        None
+    }
+}
+
+pub struct Spans<'a, K, V> {
+    inner: Iter<'a, K, V>,
+    covered: Option<&'a K>,
+    pending: Option<(&'a Segment<K>, &'a V)>,
+}
+
+impl<'a, K, V> Iterator for Spans<'a, K, V>
+where
+    K: Clone + PartialOrd
+{
+    type Item = Span<'a, K, V>;
+
+    fn next(&mut self) -> Option<Span<'a, K, V>> {
+        let (segment, value) = self.pending.take().or_else(|| self.inner.by_ref().find(|(segment, _)| !segment.is_empty()))?;
+        // if there is a gap before this segment, yield it first
+        if let Some(covered) = self.covered.take() {
+            if covered < segment.lower() {
+                self.pending = Some((segment, value));
+                return Some(Span::Gap(Segment::new(covered.clone(), segment.lower().clone())));
+            }
+        }
+        self.covered = Some(segment.upper());
+        Some(Span::Covered(segment, value))
+    }
+}
+
+// pending work for the in-order traversals, either a whole subtree or a single entry
+enum IterFrame<'a, K, V> {
+    Node(&'a SegmentMapNode<K, V>),
+    Entry(&'a Segment<K>, &'a V),
+}
+
+pub struct Iter<'a, K, V> {
+    remaining: usize,
+    frames: VecDeque<IterFrame<'a, K, V>>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a Segment<K>, &'a V);
+
+    fn next(&mut self) -> Option<(&'a Segment<K>, &'a V)> {
+        loop {
+            match self.frames.pop_front()? {
+                // if front is a subtree, expand it in order
+                IterFrame::Node(node) => {
+                    if let Some(child) = (*node.right).as_ref() {
+                        self.frames.push_front(IterFrame::Node(child));
+                    }
+                    self.frames.push_front(IterFrame::Entry(&node.segment, &node.value));
+                    if let Some(child) = (*node.left).as_ref() {
+                        self.frames.push_front(IterFrame::Node(child));
+                    }
+                },
+                IterFrame::Entry(segment, value) => {
+                    self.remaining -= 1;
+                    return Some((segment, value));
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a Segment<K>, &'a V)> {
+        loop {
+            match self.frames.pop_back()? {
+                // if back is a subtree, expand it in order
+                IterFrame::Node(node) => {
+                    if let Some(child) = (*node.left).as_ref() {
+                        self.frames.push_back(IterFrame::Node(child));
+                    }
+                    self.frames.push_back(IterFrame::Entry(&node.segment, &node.value));
+                    if let Some(child) = (*node.right).as_ref() {
+                        self.frames.push_back(IterFrame::Node(child));
+                    }
+                },
+                IterFrame::Entry(segment, value) => {
+                    self.remaining -= 1;
+                    return Some((segment, value));
+                },
+            }
+        }
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+type RangeFrame<'a, K, V> = (&'a Segment<K>, &'a V, Option<&'a SegmentMapNode<K, V>>);
+
+pub struct Range<'a, K, V> {
+    current: Option<&'a SegmentMapNode<K, V>>,
+    stack: Vec<RangeFrame<'a, K, V>>,
+    query: &'a Segment<K>,
+}
+
+impl<'a, K, V> Range<'a, K, V>
+where
+    K: PartialOrd
+{
+    fn overlaps(&self, segment: &Segment<K>) -> bool {
+        // an empty query is a point
+        if self.query.is_empty() {
+            segment.contains(self.query.lower())
+        } else {
+            (segment.lower() < self.query.upper()) && (self.query.lower() < segment.upper()) && !segment.is_empty()
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V>
+where
+    K: PartialOrd
+{
+    type Item = (&'a Segment<K>, &'a V);
+
+    fn next(&mut self) -> Option<(&'a Segment<K>, &'a V)> {
+        loop {
+            while let Some(current) = self.current.take() {
+                // only descend into subtrees that could overlap the query
+                let right = (*current.right).as_ref().filter(|_| {
+                    (current.segment.upper() < self.query.upper()) || (self.query.is_empty() && (current.segment.upper() <= self.query.upper()))
+                });
+                self.stack.push((&current.segment, &current.value, right));
+                self.current = (*current.left).as_ref().filter(|_| self.query.lower() < current.segment.lower());
+            }
+            let (segment, value, right) = self.stack.pop()?;
+            self.current = right;
+            if self.overlaps(segment) {
+                return Some((segment, value));
+            }
+        }
+    }
+}
+
+enum IterMutFrame<'a, K, V> {
+    Node(&'a mut SegmentMapNode<K, V>),
+    Entry(&'a Segment<K>, &'a mut V),
+}
+
+pub struct IterMut<'a, K, V> {
+    remaining: usize,
+    frames: VecDeque<IterMutFrame<'a, K, V>>,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a Segment<K>, &'a mut V);
+
+    fn next(&mut self) -> Option<(&'a Segment<K>, &'a mut V)> {
+        loop {
+            match self.frames.pop_front()? {
+                // if front is a subtree, expand it in order
+                IterMutFrame::Node(SegmentMapNode { segment, value, left, right, .. }) => {
+                    if let Some(child) = (**right).as_mut() {
+                        self.frames.push_front(IterMutFrame::Node(child));
+                    }
+                    self.frames.push_front(IterMutFrame::Entry(segment, value));
+                    if let Some(child) = (**left).as_mut() {
+                        self.frames.push_front(IterMutFrame::Node(child));
+                    }
+                },
+                IterMutFrame::Entry(segment, value) => {
+                    self.remaining -= 1;
+                    return Some((segment, value));
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a Segment<K>, &'a mut V)> {
+        loop {
+            match self.frames.pop_back()? {
+                // if back is a subtree, expand it in order
+                IterMutFrame::Node(SegmentMapNode { segment, value, left, right, .. }) => {
+                    if let Some(child) = (**left).as_mut() {
+                        self.frames.push_back(IterMutFrame::Node(child));
+                    }
+                    self.frames.push_back(IterMutFrame::Entry(segment, value));
+                    if let Some(child) = (**right).as_mut() {
+                        self.frames.push_back(IterMutFrame::Node(child));
+                    }
+                },
+                IterMutFrame::Entry(segment, value) => {
+                    self.remaining -= 1;
+                    return Some((segment, value));
+                },
+            }
+        }
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for IterMut<'a, K, V> {}
+
+impl<K, V> Extend<(Segment<K>, V)> for SegmentMap<K, V> 
+where
+    K: Clone + PartialOrd,
+    V: Clone,
+{
+    fn extend<I>(&mut self, iter: I) 
+    where
+        I: IntoIterator<Item = (Segment<K>, V)>
+    {
+        for (segment, value) in iter {
+            self.insert(segment, value);
+        }
+    }
+}
+
+// entries already sorted and disjoint are built directly, others are inserted in order
+impl<K, V> From<Vec<(Segment<K>, V)>> for SegmentMap<K, V>
+where
+    K: Clone + PartialOrd,
+    V: Clone,
+{
+    fn from(entries: Vec<(Segment<K>, V)>) -> SegmentMap<K, V> {
+        if is_sorted_disjoint(&entries) {
+            SegmentMap::from_sorted(entries)
+        } else {
+            let mut segment_map = SegmentMap::new();
+            segment_map.extend(entries);
+            segment_map
+        }
+    }
+}
+
+impl<K, V> IntoIterator for SegmentMap<K, V> {
+    type Item = (Segment<K>, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        IntoIter {
+            frames: self.root.map(IntoIterFrame::Node).into_iter().collect(),
+            remaining: self.len,
+        }
+    }
+}
+
+enum IntoIterFrame<K, V> {
+    Node(SegmentMapNode<K, V>),
+    Entry(Segment<K>, V),
+}
+
+pub struct IntoIter<K, V> {
+    remaining: usize,
+    frames: VecDeque<IntoIterFrame<K, V>>,
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (Segment<K>, V);
+
+    fn next(&mut self) -> Option<(Segment<K>, V)> {
+        loop {
+            match self.frames.pop_front()? {
+                // if front is a subtree, expand it in order
+                IntoIterFrame::Node(node) => {
+                    if let Some(child) = *node.right {
+                        self.frames.push_front(IntoIterFrame::Node(child));
+                    }
+                    self.frames.push_front(IntoIterFrame::Entry(node.segment, node.value));
+                    if let Some(child) = *node.left {
+                        self.frames.push_front(IntoIterFrame::Node(child));
+                    }
+                },
+                IntoIterFrame::Entry(segment, value) => {
+                    self.remaining -= 1;
+                    return Some((segment, value));
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<(Segment<K>, V)> {
+        loop {
+            match self.frames.pop_back()? {
+                // if back is a subtree, expand it in order
+                IntoIterFrame::Node(node) => {
+                    if let Some(child) = *node.left {
+                        self.frames.push_back(IntoIterFrame::Node(child));
+                    }
+                    self.frames.push_back(IntoIterFrame::Entry(node.segment, node.value));
+                    if let Some(child) = *node.right {
+                        self.frames.push_back(IntoIterFrame::Node(child));
+                    }
+                },
+                IntoIterFrame::Entry(segment, value) => {
+                    self.remaining -= 1;
+                    return Some((segment, value));
+                },
+            }
+        }
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+pub struct IntoIterRev<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+impl<K, V> Iterator for IntoIterRev<K, V> {
+    type Item = (Segment<K>, V);
+
+    fn next(&mut self) -> Option<(Segment<K>, V)> {
+        self.inner.next_back()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+pub struct IntoCoalesced<K, V> {
+    inner: IntoIter<K, V>,
+    pending: Option<(Segment<K>, V)>,
+}
+
+impl<K, V> Iterator for IntoCoalesced<K, V>
+where
+    K: Clone + PartialOrd,
+    V: PartialEq,
+{
+    type Item = (Segment<K>, V);
+
+    fn next(&mut self) -> Option<(Segment<K>, V)> {
+        for (segment, value) in self.inner.by_ref() {
+            match self.pending.take() {
+                // if segment continues the pending run, extend it
+                Some((pending_segment, pending_value)) if (pending_segment.upper() == segment.lower()) && (pending_value == value) => {
+                    self.pending = Some((Segment::new(pending_segment.lower().clone(), segment.upper().clone()), pending_value));
+                },
+                // otherwise, the pending run is complete
+                Some(pending) => {
+                    self.pending = Some((segment, value));
+                    return Some(pending);
+                },
+                None => {
+                    self.pending = Some((segment, value));
+                },
+            }
+        }
+        self.pending.take()
+    }
+}
+
+pub struct IterCoalesced<'a, K, V> {
+    inner: Iter<'a, K, V>,
+    pending: Option<(Segment<K>, &'a V)>,
+}
+
+impl<'a, K, V> Iterator for IterCoalesced<'a, K, V>
+where
+    K: Clone + PartialOrd,
+    V: PartialEq,
+{
+    type Item = (Segment<K>, &'a V);
+
+    fn next(&mut self) -> Option<(Segment<K>, &'a V)> {
+        for (segment, value) in self.inner.by_ref() {
+            match self.pending.take() {
+                // if segment continues the pending run, extend it
+                Some((pending_segment, pending_value)) if (pending_segment.upper() == segment.lower()) && (pending_value == value) => {
+                    self.pending = Some((Segment::new(pending_segment.lower().clone(), segment.upper().clone()), pending_value));
+                },
+                // otherwise, the pending run is complete
+                Some(pending) => {
+                    self.pending = Some((segment.clone(), value));
+                    return Some(pending);
+                },
+                None => {
+                    self.pending = Some((segment.clone(), value));
+                },
+            }
+        }
+        self.pending.take()
+    }
+}
+
+#[macro_export]
+macro_rules! segment_map {
+    ($($x:expr => $y:expr),* $(,)?; $capacity:expr) => {{
+        #[allow(unused_mut)]
+        let mut temp_segment_map = $crate::SegmentMap::with_capacity($capacity);
+        $(temp_segment_map.insert($x, $y);)*
+        temp_segment_map
+    }};
+    ($($x:expr => $y:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut temp_segment_map = $crate::SegmentMap::new();
+        $(temp_segment_map.insert($x, $y);)*
+        temp_segment_map
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        cmp::Ordering,
+        collections::HashSet,
+    };
+    use crate::{
+        segment_map_node::SegmentMapNode,
+        Entry,
+        Segment,
+        SegmentMap,
+        Span,
+    };
+
+    #[test]
+    fn test_insert_multiple_empty() {
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 1), 0);
+        segment_map.insert(Segment::new(1, 1), 1);
+        segment_map.insert(Segment::new(1, 1), 2);
+        assert_eq!(vec![
+            (Segment::new(0, 1), 0),
+            (Segment::new(1, 1), 2),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_insert_overwrites() {
+        // [0----|1----)---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(15, 21), 2);
+
+        // [0-|3-------------|2-)
+        segment_map.insert(Segment::new(3, 18), 3);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 18), 3),
+            (Segment::new(18, 21), 2),
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+        assert_eq!(3, segment_map.len());
+
+        // [0-|3-|4|3-------|2-)
+        segment_map.insert(Segment::new(12, 12), 5);
+        segment_map.insert(Segment::new(6, 8), 4);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 6), 3),
+            (Segment::new(6, 8), 4),
+            (Segment::new(8, 12), 3),
+            (Segment::new(12, 12), 5),
+            (Segment::new(12, 18), 3),
+            (Segment::new(18, 21), 2),
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+        assert_eq!(7, segment_map.len());
+
+        // [0-|3-|4|3-------|2-)
+        segment_map.insert(Segment::new(8, 18), 3);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 6), 3),
+            (Segment::new(6, 8), 4),
+            (Segment::new(8, 18), 3),
+            (Segment::new(18, 21), 2),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_try_insert() {
+        // [0----|1----)---[2----)
+        let mut segment_map = SegmentMap::new();
+        assert_eq!(Ok(()), segment_map.try_insert(Segment::new(0, 6), 0));
+        assert_eq!(Ok(()), segment_map.try_insert(Segment::new(6, 12), 1));
+        assert_eq!(Ok(()), segment_map.try_insert(Segment::new(15, 21), 2));
+        assert_eq!(Ok(()), segment_map.try_insert(Segment::new(12, 12), 3));
+
+        let error = segment_map.try_insert(Segment::new(3, 18), 4).unwrap_err();
+        assert_eq!(&[Segment::new(0, 6), Segment::new(6, 12), Segment::new(12, 12), Segment::new(15, 21)], error.conflicts());
+        assert_eq!(4, error.into_value());
+        assert_eq!(&[Segment::new(12, 12)], segment_map.try_insert(Segment::new(12, 12), 5).unwrap_err().conflicts());
+        assert_eq!(&[Segment::new(6, 12)], segment_map.try_insert(Segment::new(9, 9), 5).unwrap_err().conflicts());
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 1),
+            (Segment::new(12, 12), 3),
+            (Segment::new(15, 21), 2),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_gaps_with_neighbors() {
+        // [0----)-----[1----|2----)--[3-)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(12, 18), 1);
+        segment_map.insert(Segment::new(18, 24), 2);
+        segment_map.insert(Segment::new(27, 30), 3);
+        assert_eq!(vec![
+            (Segment::new(6, 12), &0, &1),
+            (Segment::new(24, 27), &2, &3)
+        ], segment_map.gaps_with_neighbors().collect::<Vec<_>>());
+
+        // ------[0----)------
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 0);
+        assert_eq!(None, segment_map.gaps_with_neighbors().next());
+    }
+
+    #[test]
+    fn test_set_range() {
+        // ---[3-----------)---
+        //                      -> [0-|3-|3----|3-|2-)
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+        segment_map.set_range(&Segment::new(3, 15), 3);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 6), 3),
+            (Segment::new(6, 12), 3),
+            (Segment::new(12, 15), 3),
+            (Segment::new(15, 18), 2)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_count_in() {
+        // [0----|1----)-----[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(18, 24), 2);
+
+        // ---[--------------)-----
+        assert_eq!(1, segment_map.count_in(&Segment::new(3, 18)));
+        assert_eq!(2, segment_map.count_overlapping(&Segment::new(3, 18)));
+
+        // [-----------------------)
+        assert_eq!(3, segment_map.count_in(&Segment::new(0, 24)));
+        assert_eq!(3, segment_map.count_overlapping(&Segment::new(0, 24)));
+
+        // ------[-----)-----------
+        assert_eq!(1, segment_map.count_in(&Segment::new(6, 12)));
+        assert_eq!(1, segment_map.count_overlapping(&Segment::new(6, 12)));
+
+        // ------------[-----)-----
+        assert_eq!(0, segment_map.count_in(&Segment::new(12, 18)));
+        assert_eq!(0, segment_map.count_overlapping(&Segment::new(12, 18)));
+
+        // ---------[)-------------
+        assert_eq!(0, segment_map.count_overlapping(&Segment::new(9, 9)));
+    }
+
+    #[test]
+    fn test_shift_range() {
+        // ---[--------)------
+        //                      -> [0-)-----[0-|1----)
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+        segment_map.shift_range(&Segment::new(3, 12), 6);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(9, 12), 0),
+            (Segment::new(12, 18), 1)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        // ------[-----)------
+        //                      -> [1----)-----[2----)
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+        segment_map.shift_range(&Segment::new(6, 12), -6);
+        assert_eq!(vec![
+            (Segment::new(0, 6), 1),
+            (Segment::new(12, 18), 2)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_ripple_insert() {
+        //    [3--)
+        //                      -> [0-|3--|0-|1----|2----)
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+        segment_map.ripple_insert(3, Segment::new(3, 7), 3);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 7), 3),
+            (Segment::new(7, 10), 0),
+            (Segment::new(10, 16), 1),
+            (Segment::new(16, 22), 2)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        //                      [3--)
+        //                      -> [0----|1----|2----)[3--)
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+        segment_map.ripple_insert(18, Segment::new(18, 22), 3);
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 1),
+            (Segment::new(12, 18), 2),
+            (Segment::new(18, 22), 3)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_ripple_delete() {
+        // ---[-----)---------
+        //                      -> [0-|1-|2----)
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+        segment_map.ripple_delete(&Segment::new(3, 9));
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 6), 1),
+            (Segment::new(6, 12), 2)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        // ------------[-----)
+        //                      -> [0----|1----)
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+        segment_map.ripple_delete(&Segment::new(12, 18));
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 1)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_inspect() {
+        // [0----|1----)-----[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(18, 24), 2);
+
+        // ---[-----------------)--
+        let info = segment_map.inspect(&Segment::new(3, 21));
+        assert_eq!(1, info.contained());
+        assert_eq!(Some(&(Segment::new(0, 6), Segment::new(3, 6))), info.lower_straddle());
+        assert_eq!(Some(&(Segment::new(18, 24), Segment::new(18, 21))), info.upper_straddle());
+        assert!(info.has_gap());
+
+        // ---[-----)--------------
+        let info = segment_map.inspect(&Segment::new(3, 9));
+        assert_eq!(0, info.contained());
+        assert_eq!(Some(&(Segment::new(0, 6), Segment::new(3, 6))), info.lower_straddle());
+        assert_eq!(Some(&(Segment::new(6, 12), Segment::new(6, 9))), info.upper_straddle());
+        assert!(!info.has_gap());
+
+        // --------[-)-------------
+        let info = segment_map.inspect(&Segment::new(8, 10));
+        assert_eq!(0, info.contained());
+        assert_eq!(Some(&(Segment::new(6, 12), Segment::new(8, 10))), info.lower_straddle());
+        assert_eq!(Some(&(Segment::new(6, 12), Segment::new(8, 10))), info.upper_straddle());
+        assert!(!info.has_gap());
+    }
+
+    #[test]
+    fn test_into_iter_rev() {
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(18, 24), 3);
+        segment_map.insert(Segment::new(12, 18), 2);
+        assert_eq!(vec![
+            (Segment::new(18, 24), 3),
+            (Segment::new(12, 18), 2),
+            (Segment::new(6, 12), 1),
+            (Segment::new(0, 6), 0)
+        ], segment_map.into_iter_rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_entry_conflicts() {
+        // [0----|1----)-----[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(18, 24), 2);
+
+        // ---[-----------)--------
+        let entry = segment_map.entry(Segment::new(3, 15));
+        assert_eq!(&Segment::new(3, 15), entry.segment());
+        match entry {
+            Entry::Occupied(entry) => assert_eq!(&[
+                (Segment::new(0, 6), 0),
+                (Segment::new(6, 12), 1)
+            ], entry.conflicts()),
+            Entry::Vacant(_) => panic!("expected an occupied entry"),
+        }
+
+        // ------------[-----)-----
+        let entry = segment_map.entry(Segment::new(12, 18));
+        assert!(entry.conflicts().is_empty());
+        assert!(matches!(entry, Entry::Vacant(_)));
+
+        // the conflicts are looked up again after modifying
+        let entry = segment_map.entry(Segment::new(3, 15)).and_modify(|value| *value += 10);
+        assert_eq!(&[
+            (Segment::new(3, 6), 10),
+            (Segment::new(6, 12), 11)
+        ], entry.conflicts());
+    }
+
+    #[test]
+    fn test_simplify_to() {
+        // [0----|1----|5----|6----)-----[9----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 5);
+        segment_map.insert(Segment::new(18, 24), 6);
+        segment_map.insert(Segment::new(30, 36), 9);
+        let merge_cost = |a: &i32, b: &i32| ((a + b) / 2, (a - b).unsigned_abs() as u64);
+
+        segment_map.simplify_to(3, merge_cost);
+        assert_eq!(vec![
+            (Segment::new(0, 12), 0),
+            (Segment::new(12, 24), 5),
+            (Segment::new(30, 36), 9)
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        segment_map.simplify_to(2, merge_cost);
+        assert_eq!(vec![
+            (Segment::new(0, 12), 0),
+            (Segment::new(12, 36), 7)
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        segment_map.simplify_to(0, merge_cost);
+        assert_eq!(vec![
+            (Segment::new(0, 36), 3)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        // [1|2|100)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 1), 1);
+        segment_map.insert(Segment::new(1, 2), 2);
+        segment_map.insert(Segment::new(2, 3), 100);
+        segment_map.simplify_to(1, |a, b| (a + b, (a + b) as u64));
+        assert_eq!(vec![
+            (Segment::new(0, 3), 103)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_is_partition_of() {
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(12, 18), 2);
+        assert!(segment_map.is_partition_of(&Segment::new(0, 18)));
+        assert!(!segment_map.is_partition_of(&Segment::new(0, 24)));
+        assert!(!segment_map.is_partition_of(&Segment::new(3, 18)));
+
+        // [0----)-----[2----)
+        segment_map.remove(&Segment::new(6, 12));
+        assert!(!segment_map.is_partition_of(&Segment::new(0, 18)));
+
+        // -------------------
+        let segment_map = SegmentMap::<i32, i32>::new();
+        assert!(segment_map.is_partition_of(&Segment::new(0, 0)));
+        assert!(!segment_map.is_partition_of(&Segment::new(0, 18)));
+    }
+
+    #[test]
+    fn test_bridge_equal() {
+        // [0----)--[0-)-[0----)---[1----)[1)-----[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(8, 11), 0);
+        segment_map.insert(Segment::new(12, 18), 0);
+        segment_map.insert(Segment::new(21, 27), 1);
+        segment_map.insert(Segment::new(27, 29), 1);
+        segment_map.insert(Segment::new(35, 41), 1);
+        segment_map.bridge_equal(2u32);
+        assert_eq!(vec![
+            (Segment::new(0, 18), 0),
+            (Segment::new(21, 29), 1),
+            (Segment::new(35, 41), 1)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        // [0----)--[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(8, 14), 1);
+        segment_map.bridge_equal(2u32);
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(8, 14), 1)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_evaluator() {
+        // [0----|1----)-----[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(18, 24), 2);
+        let evaluate = segment_map.evaluator();
+        assert_eq!(vec![Some(&0), Some(&0), Some(&1), Some(&1), None, Some(&2), None], [0, 5, 6, 11, 12, 18, 24].iter().map(&evaluate).collect::<Vec<_>>());
+        assert_eq!(Some(&0), evaluate(&3));
+    }
+
+    #[test]
+    fn test_locality_cursor() {
+        // [0----|1----)-----[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(18, 24), 2);
+        let mut cursor = segment_map.locality_cursor();
+        assert_eq!((-3..27).map(|key| segment_map.get(&key)).collect::<Vec<_>>(), (-3..27).map(|key| cursor.get(&key)).collect::<Vec<_>>());
+        assert_eq!(vec![None, Some(&2), Some(&0), None, Some(&1)], [24, 20, 3, 15, 8].iter().map(|key| cursor.get(key)).collect::<Vec<_>>());
+
+        // [0-|1-|2-|3-|4-|5-|6-|7-|8-|9-)
+        let mut segment_map = SegmentMap::new();
+        for value in 0..10 {
+            segment_map.insert(Segment::new(value * 4, value * 4 + 4), value);
+        }
+        for first in 0..40 {
+            for second in 0..40 {
+                let mut cursor = segment_map.locality_cursor();
+                cursor.get(&39);
+                assert_eq!(segment_map.get(&first), cursor.get(&first));
+                assert_eq!(segment_map.get(&second), cursor.get(&second));
+                assert_eq!(segment_map.get(&(second + 4)), cursor.get(&(second + 4)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_erode() {
+        // [0----|1----)--[2)-[3----)
+        //                            -> -[0-)-[1-)--------[3-)-
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(14, 16), 2);
+        segment_map.insert(Segment::new(17, 23), 3);
+        segment_map.erode(1u32);
+        assert_eq!(vec![
+            (Segment::new(1, 5), 0),
+            (Segment::new(7, 11), 1),
+            (Segment::new(18, 22), 3)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        // [0----)-------------[1)
+        //                            -> ----------------------
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0u8, 6), 0);
+        segment_map.insert(Segment::new(253, 255), 1);
+        segment_map.erode(3);
+        assert!(segment_map.is_empty());
+    }
+
+    #[test]
+    fn test_dilate() {
+        // ---[0-|1-)----[2)-----[3----)
+        //                               -> --[1------)--[2---)-[3------)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(3, 6), 0);
+        segment_map.insert(Segment::new(6, 9), 1);
+        segment_map.insert(Segment::new(13, 15), 2);
+        segment_map.insert(Segment::new(20, 26), 3);
+        segment_map.dilate(1u32, |a, b| a + b);
+        assert_eq!(vec![
+            (Segment::new(2, 10), 1),
+            (Segment::new(12, 16), 2),
+            (Segment::new(19, 27), 3)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        // [0)-[1)-[2)
+        //                               -> [3---------)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0u8, 2), 0);
+        segment_map.insert(Segment::new(4, 6), 1);
+        segment_map.insert(Segment::new(8, 10), 2);
+        segment_map.dilate(2, |a, b| a + b);
+        assert_eq!(vec![
+            (Segment::new(0, 12), 3)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_into_coalesced() {
+        // [0----|0----|1----)-----[1----|1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 0);
+        segment_map.insert(Segment::new(12, 18), 1);
+        segment_map.insert(Segment::new(24, 30), 1);
+        segment_map.insert(Segment::new(30, 36), 1);
+        assert_eq!(vec![
+            (Segment::new(0, 12), 0),
+            (Segment::new(12, 18), 1),
+            (Segment::new(24, 36), 1)
+        ], segment_map.into_coalesced().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_iter_coalesced() {
+        // [0----|0----|1----)-----[1----|1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 0);
+        segment_map.insert(Segment::new(12, 18), 1);
+        segment_map.insert(Segment::new(24, 30), 1);
+        segment_map.insert(Segment::new(30, 36), 1);
+        assert_eq!(vec![
+            (Segment::new(0, 12), &0),
+            (Segment::new(12, 18), &1),
+            (Segment::new(24, 36), &1)
+        ], segment_map.iter_coalesced().collect::<Vec<_>>());
+        assert_eq!(5, segment_map.len());
+    }
+
+    #[test]
+    fn test_drop_shorter_than() {
+        // [0----|1)[2--)--[3)[4----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 8), 1);
+        segment_map.insert(Segment::new(8, 12), 2);
+        segment_map.insert(Segment::new(14, 16), 3);
+        segment_map.insert(Segment::new(16, 22), 4);
+        segment_map.drop_shorter_than(4u32);
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(8, 12), 2),
+            (Segment::new(16, 22), 4)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_to_rle() {
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(12, 18), 2);
+        assert_eq!(vec![(0, 0), (6, 1), (12, 2)], segment_map.to_rle());
+    }
+
+    #[test]
+    fn test_from_rle() {
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 1),
+            (Segment::new(12, 18), 2)
+        ], SegmentMap::from_rle(vec![(0, 0), (6, 1), (12, 2)], 18).into_iter().collect::<Vec<_>>());
+        assert!(SegmentMap::<i32, i32>::from_rle(vec![], 18).is_empty());
+
+        // round trip a variety of contiguous maps
+        let mut seed = 1u64;
+        for len in 1..32 {
+            let mut segment_map = SegmentMap::new();
+            let mut lower = 0;
+            for _ in 0..len {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let upper = lower + 1 + (seed >> 59);
+                segment_map.insert(Segment::new(lower, upper), seed >> 62);
+                lower = upper;
+            }
+            let expected = segment_map.clone().into_iter().collect::<Vec<_>>();
+            assert_eq!(expected, SegmentMap::from_rle(segment_map.to_rle(), lower).into_iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_extend() {
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.par_extend(vec![
+            (Segment::new(18, 24), 3),
+            (Segment::new(0, 6), 0),
+            (Segment::new(12, 18), 2)
+        ]);
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 1),
+            (Segment::new(12, 18), 2),
+            (Segment::new(18, 24), 3)
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        // ---[4-----)-------------
+        //     ---------[6----)----
+        //         [7----)---------
+        let pairs = vec![
+            (Segment::new(3, 9), 4),
+            (Segment::new(12, 12), 5),
+            (Segment::new(10, 16), 6),
+            (Segment::new(7, 13), 7),
+            (Segment::new(20, 20), 8),
+        ];
+        let mut expected = segment_map.clone();
+        expected.extend(pairs.clone());
+        segment_map.par_extend(pairs);
+        assert_eq!(expected, segment_map);
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        let snapshot = segment_map.clone();
+
+        let result: Result<(), &str> = segment_map.transaction(|segment_map| {
+            segment_map.remove(&Segment::new(3, 9));
+            Err("rollback")
+        });
+        assert_eq!(Err("rollback"), result);
+        assert_eq!(snapshot, segment_map);
+
+        let result: Result<usize, &str> = segment_map.transaction(|segment_map| {
+            segment_map.remove(&Segment::new(3, 9));
+            Ok(segment_map.iter().count())
+        });
+        assert_eq!(Ok(2), result);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(9, 12), 1)
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_iter_spans() {
+        // [0----|1----)-----[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(18, 24), 2);
+        assert_eq!(vec![
+            Span::Covered(&Segment::new(0, 6), &0),
+            Span::Covered(&Segment::new(6, 12), &1),
+            Span::Gap(Segment::new(12, 18)),
+            Span::Covered(&Segment::new(18, 24), &2)
+        ], segment_map.iter_spans().collect::<Vec<_>>());
+        assert_eq!(None, SegmentMap::<i32, i32>::new().iter_spans().next());
+
+        // markers are skipped, so the gaps agree with `gaps`
+        // [0----|1----[4)--[3)--[2----)
+        segment_map.insert(Segment::new(15, 15), 3);
+        segment_map.insert(Segment::new(12, 12), 4);
+        assert_eq!(vec![
+            Span::Covered(&Segment::new(0, 6), &0),
+            Span::Covered(&Segment::new(6, 12), &1),
+            Span::Gap(Segment::new(12, 18)),
+            Span::Covered(&Segment::new(18, 24), &2)
+        ], segment_map.iter_spans().collect::<Vec<_>>());
+        assert_eq!(segment_map.gaps().collect::<Vec<_>>(), segment_map.iter_spans().filter_map(|span| match span {
+            Span::Gap(gap) => Some(gap),
+            Span::Covered(..) => None,
+        }).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_replace_with() {
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        let mut other = SegmentMap::new();
+        other.insert(Segment::new(6, 12), 1);
+        let old = segment_map.replace_with(other);
+        assert_eq!(vec![(Segment::new(0, 6), 0)], old.into_iter().collect::<Vec<_>>());
+        assert_eq!(vec![(Segment::new(6, 12), 1)], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_is_aligned_with() {
+        // [0----|1----|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+
+        // ---[a-|b----|c----|d----)
+        let mut other = SegmentMap::new();
+        other.insert(Segment::new(3, 6), 'a');
+        other.insert(Segment::new(6, 12), 'b');
+        other.insert(Segment::new(12, 18), 'c');
+        other.insert(Segment::new(18, 24), 'd');
+        assert!(segment_map.is_aligned_with(&other));
+
+        // ---[a-|b----|c-|e-|d----)
+        other.update(&Segment::new(15, 18), |_| Some('e'));
+        assert!(!segment_map.is_aligned_with(&other));
+    }
+
+    #[test]
+    fn test_overlay_by_priority() {
+        // [3----|1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 3);
+        segment_map.insert(Segment::new(6, 12), 1);
+
+        // ---[2----|1----|2-)
+        let mut other = SegmentMap::new();
+        other.insert(Segment::new(3, 9), 2);
+        other.insert(Segment::new(9, 15), 1);
+        other.insert(Segment::new(15, 18), 2);
+
+        // [3-|3-|2-|1-|1-|2-)
+        segment_map.overlay_by_priority(&other, |&value| value);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 3),
+            (Segment::new(3, 6), 3),
+            (Segment::new(6, 9), 2),
+            (Segment::new(9, 12), 1),
+            (Segment::new(12, 15), 1),
+            (Segment::new(15, 18), 2),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_key_range() {
+        let mut segment_map = SegmentMap::new();
+        assert_eq!(None, segment_map.key_range());
+
+        // ---[0----|1----)
+        segment_map.insert(Segment::new(9, 15), 1);
+        segment_map.insert(Segment::new(3, 9), 0);
+        assert_eq!(Some((&3, &15)), segment_map.key_range());
+    }
+
+    #[test]
+    fn test_fill_small_gaps() {
+        // [0-|---|1----|------|2-)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 3), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(18, 21), 2);
+
+        // [0-|9-|1----|------|2-)
+        segment_map.fill_small_gaps(3u32, 9);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 6), 9),
+            (Segment::new(6, 12), 1),
+            (Segment::new(18, 21), 2),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_transitions() {
+        // [0-|0-|1----|---|1-|2-)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 3), 0);
+        segment_map.insert(Segment::new(3, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(15, 18), 1);
+        segment_map.insert(Segment::new(18, 21), 2);
+
+        assert_eq!(vec![&6, &18], segment_map.transitions().collect::<Vec<_>>());
+        assert_eq!(2, segment_map.transition_count());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_retain_values_in() {
+        // [0-|1-|2-|0-|1-)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 3), 0);
+        segment_map.insert(Segment::new(3, 6), 1);
+        segment_map.insert(Segment::new(6, 9), 2);
+        segment_map.insert(Segment::new(9, 12), 0);
+        segment_map.insert(Segment::new(12, 15), 1);
+        let values = vec![0, 2].into_iter().collect::<HashSet<_>>();
+
+        // [0-)---[2-|0-)---
+        let mut retained = segment_map.clone();
+        retained.retain_values_in(&values);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(6, 9), 2),
+            (Segment::new(9, 12), 0),
+        ], retained.into_iter().collect::<Vec<_>>());
+
+        // ---[1-)------[1-)
+        segment_map.remove_values_in(&values);
+        assert_eq!(vec![
+            (Segment::new(3, 6), 1),
+            (Segment::new(12, 15), 1),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_smooth() {
+        // [0-|3-|6-)---[9-)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 3), 0);
+        segment_map.insert(Segment::new(3, 6), 3);
+        segment_map.insert(Segment::new(6, 9), 6);
+        segment_map.insert(Segment::new(12, 15), 9);
+
+        // [1-|3-|4-)---[9-)
+        segment_map.smooth(|left, value, right| {
+            let values = vec![left, Some(value), right].into_iter().flatten().copied().collect::<Vec<_>>();
+            values.iter().sum::<i32>() / values.len() as i32
+        });
+        assert_eq!(vec![
+            (Segment::new(0, 3), 1),
+            (Segment::new(3, 6), 3),
+            (Segment::new(6, 9), 4),
+            (Segment::new(12, 15), 9),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_split_largest() {
+        let mut segment_map: SegmentMap<f64, f64> = SegmentMap::new();
+        assert!(!segment_map.split_largest(|_, &value| (0.0, value, value)));
+
+        // [1--------------|1-)
+        segment_map.insert(Segment::new(0.0, 6.0), 1.0);
+        segment_map.insert(Segment::new(6.0, 7.0), 1.0);
+
+        // refine until no segment is wider than 2
+        while segment_map.segments().any(|segment| segment.width() > 2.0) {
+            assert!(segment_map.split_largest(|segment, &value| {
+                ((segment.lower() + segment.upper()) / 2.0, value / 2.0, value / 2.0)
+            }));
+        }
+
+        // [.25|.25|.25|.25|1-)
+        assert_eq!(vec![
+            (Segment::new(0.0, 1.5), 0.25),
+            (Segment::new(1.5, 3.0), 0.25),
+            (Segment::new(3.0, 4.5), 0.25),
+            (Segment::new(4.5, 6.0), 0.25),
+            (Segment::new(6.0, 7.0), 1.0),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        // markers alone are never split
+        // [1)  [2)
+        let mut segment_map: SegmentMap<f64, f64> = SegmentMap::new();
+        segment_map.insert(Segment::new(0.0, 0.0), 1.0);
+        segment_map.insert(Segment::new(3.0, 3.0), 2.0);
+        assert!(!segment_map.split_largest(|segment, &value| (*segment.lower(), value, value)));
+        assert_eq!(vec![
+            (Segment::new(0.0, 0.0), 1.0),
+            (Segment::new(3.0, 3.0), 2.0),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+
+        // splitting at an endpoint (where a marker already sits) is rejected
+        // [1)[2--)
+        let mut segment_map: SegmentMap<f64, f64> = SegmentMap::new();
+        segment_map.insert(Segment::new(0.0, 0.0), 1.0);
+        segment_map.insert(Segment::new(0.0, 2.0), 2.0);
+        assert!(!segment_map.split_largest(|segment, &value| (*segment.lower(), value, value)));
+        assert!(!segment_map.split_largest(|segment, &value| (*segment.upper(), value, value)));
+        assert_eq!(vec![
+            (Segment::new(0.0, 0.0), 1.0),
+            (Segment::new(0.0, 2.0), 2.0),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_merge_smallest() {
+        // [0----|1-|2|3-)----[4)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 9), 1);
+        segment_map.insert(Segment::new(9, 10), 2);
+        segment_map.insert(Segment::new(10, 13), 3);
+        segment_map.insert(Segment::new(17, 18), 4);
+
+        // [0----|12--|3-)----[4)
+        assert!(segment_map.merge_smallest(|left, right| left * 10 + right));
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 10), 12),
+            (Segment::new(10, 13), 3),
+            (Segment::new(17, 18), 4),
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        // [123--------)----[4)
+        assert!(segment_map.merge_smallest(|left, right| left * 10 + right));
+        assert!(segment_map.merge_smallest(|left, right| left * 10 + right));
+        assert_eq!(vec![
+            (Segment::new(0, 13), 123),
+            (Segment::new(17, 18), 4),
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        assert!(!segment_map.merge_smallest(|left, right| left * 10 + right));
+    }
+
+    #[test]
+    fn test_iter_between() {
+        // [0----|1----)---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 12), 3);
+        segment_map.insert(Segment::new(15, 21), 2);
+
+        assert_eq!(vec![
+            (&Segment::new(6, 12), &1),
+            (&Segment::new(15, 21), &2),
+        ], segment_map.iter_between(&6, &16).collect::<Vec<_>>());
+        assert_eq!(vec![
+            (&Segment::new(0, 6), &0),
+        ], segment_map.iter_between(&3, &4).collect::<Vec<_>>());
+        assert_eq!(0, segment_map.iter_between(&12, &15).count());
+        assert_eq!(0, segment_map.iter_between(&4, &3).count());
+
+        // [0-)-[1-)-[2-)-[3-)-[4-)-[5-)-[6-)-[7-)-[8-)-[9-)
+        let mut segment_map = SegmentMap::new();
+        for value in 0..10 {
+            segment_map.insert(Segment::new(value * 6, value * 6 + 4), value);
+        }
+        for lo in 0..60 {
+            for hi in (lo + 1)..60 {
+                assert_eq!(
+                    segment_map.iter().filter(|(segment, _)| (segment.lower() < &hi) && (&lo < segment.upper())).collect::<Vec<_>>(),
+                    segment_map.iter_between(&lo, &hi).collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_check_invariants() {
+        let mut segment_map = SegmentMap::new();
+        assert_eq!(Ok(()), segment_map.check_invariants());
+
+        // [0----|1----)---[2----)
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(15, 15), 3);
+        segment_map.insert(Segment::new(15, 21), 2);
+        assert_eq!(Ok(()), segment_map.check_invariants());
+
+        // [0----|1-[2----)--)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        let mut entries = segment_map.into_iter().collect::<Vec<_>>();
+        entries.push((Segment::new(9, 15), 2));
+        let mut segment_map = SegmentMap::new();
+        segment_map.rebuild(entries);
+        assert!(segment_map.check_invariants().is_err());
+    }
+
+    #[test]
+    fn test_get_key_value() {
+        // [0----)---|---[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(9, 9), 2);
+        segment_map.insert(Segment::new(12, 18), 1);
+
+        assert_eq!(Some((&Segment::new(0, 6), &0)), segment_map.get_key_value(&0));
+        assert_eq!(None, segment_map.get_key_value(&6));
+        assert_eq!(None, segment_map.get_key_value(&9));
+        assert_eq!(Some((&Segment::new(12, 18), &1)), segment_map.get_key_value(&17));
+        for key in 0..20 {
+            assert_eq!(segment_map.get_entry(&key), segment_map.get_key_value(&key));
+        }
+    }
+
+    #[test]
+    fn test_first_last() {
+        let mut segment_map = SegmentMap::new();
+        assert_eq!(None, segment_map.first_key_value());
+        assert_eq!(None, segment_map.last_entry_mut());
+
+        // |---[0----)---[1----)---[2----)
+        for i in vec![1, 2, 0].into_iter() {
+            segment_map.insert(Segment::new(3 + 9 * i, 9 + 9 * i), i);
+        }
+        segment_map.insert(Segment::new(0, 0), 3);
+        assert_eq!(Some((&Segment::new(0, 0), &3)), segment_map.first_key_value());
+        assert_eq!(Some((&Segment::new(21, 27), &2)), segment_map.last_key_value());
+
+        *segment_map.last_entry_mut().unwrap().1 += 3;
+        *segment_map.first_entry_mut().unwrap().1 += 1;
+        assert_eq!(vec![&4, &0, &1, &5], segment_map.values().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_floor_ceiling() {
+        // [0----)------[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(12, 18), 1);
+
+        assert_eq!(Some((&Segment::new(0, 6), &0)), segment_map.floor_entry(&9));
+        assert_eq!(Some((&Segment::new(12, 18), &1)), segment_map.ceiling_entry(&9));
+        assert_eq!(Some((&Segment::new(0, 6), &0)), segment_map.floor_entry(&0));
+        assert_eq!(Some((&Segment::new(0, 6), &0)), segment_map.ceiling_entry(&0));
+        assert_eq!(Some((&Segment::new(12, 18), &1)), segment_map.floor_entry(&15));
+        assert_eq!(None, segment_map.ceiling_entry(&15));
+        assert_eq!(None, segment_map.floor_entry(&-1));
+
+        // [0----)|-----[1----)
+        segment_map.insert(Segment::new(6, 6), 2);
+        assert_eq!(Some((&Segment::new(6, 6), &2)), segment_map.floor_entry(&9));
+        assert_eq!(Some((&Segment::new(6, 6), &2)), segment_map.ceiling_entry(&3));
+    }
+
+    #[test]
+    fn test_get_mut() {
+        // [0----|1----)---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(15, 21), 2);
+
+        // [0----|5----)---[3----)
+        *segment_map.get_mut(&6).unwrap() = 5;
+        assert_eq!(None, segment_map.get_mut(&12));
+        if let Some((segment, value)) = segment_map.get_entry_mut(&20) {
+            assert_eq!(&Segment::new(15, 21), segment);
+            *value += 1;
+        }
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 5),
+            (Segment::new(15, 21), 3),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_range() {
+        // [0----|1----)---[2----|3----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(15, 15), 4);
+        segment_map.insert(Segment::new(15, 21), 2);
+        segment_map.insert(Segment::new(21, 27), 3);
+
+        assert_eq!(vec![
+            (&Segment::new(6, 12), &1),
+            (&Segment::new(15, 21), &2),
+        ], segment_map.range(&Segment::new(9, 18)).collect::<Vec<_>>());
+        assert_eq!(vec![
+            (&Segment::new(0, 6), &0),
+            (&Segment::new(6, 12), &1),
+            (&Segment::new(15, 21), &2),
+            (&Segment::new(21, 27), &3),
+        ], segment_map.range(&Segment::new(0, 27)).collect::<Vec<_>>());
+        assert_eq!(0, segment_map.range(&Segment::new(12, 15)).count());
+
+        // an empty query is a point
+        assert_eq!(vec![(&Segment::new(6, 12), &1)], segment_map.range(&Segment::new(6, 6)).collect::<Vec<_>>());
+        assert_eq!(vec![(&Segment::new(15, 21), &2)], segment_map.range(&Segment::new(15, 15)).collect::<Vec<_>>());
+        assert_eq!(0, segment_map.range(&Segment::new(27, 27)).count());
+
+        // every query agrees with a full scan
+        for lower in 0..28 {
+            for upper in lower..28 {
+                let query = Segment::new(lower, upper);
+                let expected = segment_map.iter().filter(|(segment, _)| {
+                    if query.is_empty() { segment.contains(&lower) } else { segment.intersection(&query).is_some() }
+                }).collect::<Vec<_>>();
+                assert_eq!(expected, segment_map.range(&query).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn test_overlapping() {
+        // [0----|1----)---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(15, 21), 2);
+
+        assert_eq!(vec![(&Segment::new(6, 12), &1)], segment_map.overlapping(&Segment::new(6, 9)).collect::<Vec<_>>());
+        assert_eq!(vec![
+            (&Segment::new(0, 6), &0),
+            (&Segment::new(6, 12), &1),
+        ], segment_map.overlapping(&Segment::new(5, 7)).collect::<Vec<_>>());
+        assert_eq!(0, segment_map.overlapping(&Segment::new(12, 15)).count());
+        assert_eq!(0, segment_map.overlapping(&Segment::new(9, 9)).count());
+    }
+
+    #[test]
+    fn test_remove_and_collect() {
+        // [0----|1----)---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 12), 3);
+        segment_map.insert(Segment::new(15, 21), 2);
+
+        // [0|--|0|1----)---[2----)
+        assert_eq!(vec![(Segment::new(2, 4), 0)], segment_map.remove_and_collect(&Segment::new(2, 4)));
+
+        // [0|--|0|1-)---------[2-)
+        assert_eq!(vec![
+            (Segment::new(9, 12), 1),
+            (Segment::new(15, 18), 2),
+        ], segment_map.remove_and_collect(&Segment::new(9, 18)));
+        assert_eq!(vec![(Segment::new(12, 12), 3)], segment_map.remove_and_collect(&Segment::new(12, 12)));
+        assert!(segment_map.remove_and_collect(&Segment::new(7, 7)).is_empty());
+        assert_eq!(vec![
+            (Segment::new(0, 2), 0),
+            (Segment::new(4, 6), 0),
+            (Segment::new(6, 7), 1),
+            (Segment::new(7, 9), 1),
+            (Segment::new(18, 21), 2),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_random_edits() {
+        // compare against a naive per-key model
+        let mut seed = 7u64;
+        let mut random = |bound: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        for _ in 0..64 {
+            let mut segment_map = SegmentMap::new();
+            let mut model = [None; 40];
+            for step in 0..32 {
+                let lower = random(40);
+                let upper = lower + 1 + random(40 - lower);
+                let segment = Segment::new(lower, upper);
+                let value = match random(3) {
+                    0 => { segment_map.insert(segment, step); Some(step) },
+                    1 => { segment_map.remove(&segment); None },
+                    _ => { segment_map.update(&segment, |_| Some(step)); Some(step) },
+                };
+                for key in lower..upper {
+                    model[key as usize] = value;
+                }
+                for key in 0..40 {
+                    assert_eq!(model[key as usize].as_ref(), segment_map.get(&key));
+                }
+                let entries = segment_map.iter().collect::<Vec<_>>();
+                assert_eq!(entries.len(), segment_map.len());
+                assert!(entries.windows(2).all(|pair| pair[0].0.upper() <= pair[1].0.lower()));
+                assert!(segment_map.root.as_ref().is_none_or(|root| root.checked_height().is_some()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_balanced() {
+        let height = |segment_map: &SegmentMap<u32, u32>| segment_map.root.as_ref().and_then(|root| root.checked_height());
+
+        // ascending inserts would degrade an unbalanced tree into a list
+        let mut segment_map = SegmentMap::new();
+        for i in 0..10_000 {
+            segment_map.insert(Segment::new(2 * i, 2 * i + 1), i);
+        }
+        assert!(height(&segment_map).unwrap() <= 20);
+
+        // removing a wide range from one side leaves the tree balanced
+        segment_map.remove(&Segment::new(0, 15_000));
+        assert_eq!(2_500, segment_map.len());
+        assert!(height(&segment_map).unwrap() <= 17);
+
+        // descending inserts, with every other segment split by an update
+        let mut segment_map = SegmentMap::new();
+        for i in (0..10_000).rev() {
+            segment_map.insert(Segment::new(4 * i, 4 * i + 3), i);
+        }
+        for i in (0..10_000).step_by(2) {
+            segment_map.update(&Segment::new(4 * i + 1, 4 * i + 2), |_| Some(0));
+        }
+        assert_eq!(20_000, segment_map.len());
+        assert!(height(&segment_map).unwrap() <= 22);
+        assert_eq!(30_000, segment_map.total_covered());
+    }
+
+    #[test]
+    fn test_rebalance() {
+        let height = |segment_map: &SegmentMap<u32, u32>| segment_map.root.as_ref().and_then(|root| root.checked_height());
+
+        // ascending inserts leave the tree balanced, but not as short as it could be
+        let mut segment_map = SegmentMap::new();
+        for i in 0..1_000 {
+            segment_map.insert(Segment::new(2 * i, 2 * i + 1), i);
+        }
+        let before = segment_map.clone();
+        segment_map.rebalance();
+        assert_eq!(Some(10), height(&segment_map));
+        assert_eq!(before, segment_map);
+        assert_eq!(1_000, segment_map.len());
+
+        let mut segment_map = SegmentMap::<u32, u32>::new();
+        segment_map.rebalance();
+        assert!(segment_map.is_empty());
+    }
+
+    #[test]
+    fn test_get_large() {
+        let mut segment_map = SegmentMap::new();
+        for i in 0..100_000 {
+            segment_map.insert(Segment::new(2 * i, 2 * i + 1), i);
+        }
+        assert_eq!(Some(&0), segment_map.get(&0));
+        assert_eq!(Some(&99_999), segment_map.get(&199_998));
+        assert_eq!(None, segment_map.get(&199_999));
+        *segment_map.get_mut(&100_000).unwrap() = 0;
+        assert_eq!(Some(&0), segment_map.get(&100_000));
+    }
+
+    #[test]
+    fn test_from_sorted() {
+        // [0----|1----)---|---[2----)
+        let segment_map = SegmentMap::from_sorted(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 1),
+            (Segment::new(15, 15), 3),
+            (Segment::new(18, 24), 2),
+        ]);
+        assert_eq!(4, segment_map.len());
+        assert_eq!(Some(&1), segment_map.get(&6));
+        assert!(segment_map.root.as_ref().unwrap().checked_height().is_some());
+
+        let segment_map = SegmentMap::from_sorted((0..10_000).map(|i| (Segment::new(i, i + 1), i)));
+        assert_eq!(Some(14), segment_map.root.as_ref().unwrap().checked_height());
+    }
+
+    #[test]
+    #[should_panic(expected = "segments must be sorted and must not overlap")]
+    fn test_from_sorted_overlapping() {
+        // [0----|1-[2----)--)
+        SegmentMap::from_sorted(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 1),
+            (Segment::new(9, 15), 2),
+        ]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        // [0----|1----)---|---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(18, 24), 2);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(15, 15), 3);
+        segment_map.insert(Segment::new(6, 12), 1);
+
+        let json = serde_json::to_string(&segment_map).unwrap();
+        assert_eq!("[[[0,6],0],[[6,12],1],[[15,15],3],[[18,24],2]]", json);
+        assert_eq!(segment_map, serde_json::from_str::<SegmentMap<i32, i32>>(&json).unwrap());
+        assert_eq!(SegmentMap::new(), serde_json::from_str::<SegmentMap<i32, i32>>("[]").unwrap());
+
+        // [0----|1-[2----)--)
+        assert!(serde_json::from_str::<SegmentMap<i32, i32>>("[[[0,6],0],[[6,12],1],[[9,15],2]]").is_err());
+        // out of order
+        assert!(serde_json::from_str::<SegmentMap<i32, i32>>("[[[6,12],1],[[0,6],0]]").is_err());
+        // duplicate empty segments
+        assert!(serde_json::from_str::<SegmentMap<i32, i32>>("[[[3,3],0],[[3,3],1]]").is_err());
+    }
+
+    #[test]
+    fn test_entry() {
+        // [0----)------[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(12, 18), 1);
+
+        // [0----|5-----[1----)
+        segment_map.entry(Segment::new(3, 15)).or_insert(5);
+        assert_eq!(vec![
+            (&Segment::new(0, 6), &0),
+            (&Segment::new(6, 12), &5),
+            (&Segment::new(12, 18), &1),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // [0-|10|15----|11|1-)
+        let mut visited = Vec::new();
+        segment_map.entry(Segment::new(3, 15)).and_modify(|value| {
+            visited.push(*value);
+            *value += 10;
+        });
+        assert_eq!(vec![0, 5, 1], visited);
+        assert_eq!(vec![
+            (&Segment::new(0, 3), &0),
+            (&Segment::new(3, 6), &10),
+            (&Segment::new(6, 12), &15),
+            (&Segment::new(12, 15), &11),
+            (&Segment::new(15, 18), &1),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // fully covered, so default is never called
+        segment_map.entry(Segment::new(0, 18)).or_insert_with(|| panic!("covered"));
+
+        // [0-|10|15----|11|2-|2--------)
+        segment_map.entry(Segment::new(15, 27)).and_modify(|value| *value += 1).or_insert(2);
+        assert_eq!(vec![
+            (&Segment::new(15, 18), &2),
+            (&Segment::new(18, 27), &2),
+        ], segment_map.range(&Segment::new(15, 27)).collect::<Vec<_>>());
+
+        // empty segments are only inserted if vacant
+        segment_map.entry(Segment::new(4, 4)).or_insert(3);
+        segment_map.entry(Segment::new(30, 30)).or_insert(3);
+        segment_map.entry(Segment::new(30, 30)).or_insert(4);
+        assert_eq!(None, segment_map.root.as_ref().unwrap().get_exact(&Segment::new(4, 4)));
+        assert_eq!(Some(&3), segment_map.root.as_ref().unwrap().get_exact(&Segment::new(30, 30)));
+    }
+
+    #[test]
+    fn test_update_entry_order() {
+        // [0----)---[1----)|--[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(18, 24), 2);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(9, 15), 1);
+        segment_map.insert(Segment::new(16, 16), 3);
+
+        // [0-|4-|4-|4-----|4||4-|4-|2-)
+        let mut pieces = Vec::new();
+        segment_map.update_entry(&Segment::new(3, 21), |segment, value| {
+            pieces.push((*segment, value));
+            Some(4)
+        });
+        assert_eq!(vec![
+            (Segment::new(3, 6), Some(0)),
+            (Segment::new(6, 9), None),
+            (Segment::new(9, 15), Some(1)),
+            (Segment::new(15, 16), None),
+            (Segment::new(16, 18), None),
+            (Segment::new(18, 21), Some(2)),
+        ], pieces);
+
+        // the order holds however the tree happens to be shaped
+        let mut seed = 11u64;
+        let mut random = |bound: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        for _ in 0..64 {
+            let mut segment_map = SegmentMap::new();
+            for _ in 0..16 {
+                let lower = random(64);
+                segment_map.insert(Segment::new(lower, lower + 1 + random(4)), 0);
+            }
+            let mut pieces = Vec::new();
+            segment_map.update_entry(&Segment::new(8, 56), |segment, value| {
+                pieces.push(*segment);
+                value
+            });
+            assert_eq!(&8, pieces.first().unwrap().lower());
+            assert_eq!(&56, pieces.last().unwrap().upper());
+            assert!(pieces.windows(2).all(|pair| pair[0].upper() == pair[1].lower()));
+        }
+    }
+
+    #[test]
+    fn test_remove_with() {
+        #[derive(Debug, PartialEq)]
+        struct Unclonable(u32);
+
+        // [0----------)---[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert_disjoint(Segment::new(0, 12), Unclonable(0));
+        segment_map.insert_disjoint(Segment::new(15, 21), Unclonable(1));
+        let splits = Cell::new(0);
+        let split = |value: &Unclonable| {
+            splits.set(splits.get() + 1);
+            Unclonable(value.0 + 10)
+        };
+
+        // [0-------)------[1----)
+        segment_map.remove_with(&Segment::new(9, 15), split);
+        assert_eq!(0, splits.get());
+
+        // [10)--[0-)------[1----)
+        segment_map.remove_with(&Segment::new(3, 6), split);
+        assert_eq!(1, splits.get());
+        assert_eq!(vec![
+            (&Segment::new(0, 3), &Unclonable(10)),
+            (&Segment::new(6, 9), &Unclonable(0)),
+            (&Segment::new(15, 21), &Unclonable(1)),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // [10)--[0-)---------[1-)
+        segment_map.remove_with(&Segment::new(12, 18), split);
+        assert_eq!(1, splits.get());
+        assert_eq!(3, segment_map.len());
+    }
+
+    #[test]
+    fn test_insert_many() {
+        // [0--------------)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 16), 0);
+
+        // [0)[1)[0)[2-)[0)[3--)
+        segment_map.insert_many(vec![
+            (Segment::new(2, 4), 1),
+            (Segment::new(6, 9), 2),
+            (Segment::new(11, 16), 3),
+            (Segment::new(18, 20), 4),
+        ]);
+        assert_eq!(vec![
+            (&Segment::new(0, 2), &0),
+            (&Segment::new(2, 4), &1),
+            (&Segment::new(4, 6), &0),
+            (&Segment::new(6, 9), &2),
+            (&Segment::new(9, 11), &0),
+            (&Segment::new(11, 16), &3),
+            (&Segment::new(18, 20), &4),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // agrees with inserting one at a time, empty segments included
+        let mut seed = 29u64;
+        let mut random = |bound: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        for round in 0..500 {
+            let mut expected = SegmentMap::new();
+            for i in 0..8 {
+                let lower = random(30);
+                expected.insert(Segment::new(lower, lower + random(6)), i);
+            }
+            let mut segment_map = expected.clone();
+            let mut batch = Vec::new();
+            let mut lower = random(4);
+            while lower < 36 {
+                let upper = lower + random(5);
+                batch.push((Segment::new(lower, upper), 100 + round));
+                // an empty segment must not be followed by an identical one
+                lower = upper + random(3) + if lower == upper { 1 } else { 0 };
+            }
+            for (segment, value) in batch.iter().cloned() {
+                expected.insert(segment, value);
+            }
+            segment_map.insert_many(batch);
+            assert_eq!(expected, segment_map);
+            assert!(segment_map.root.as_ref().is_none_or(|root| root.checked_height().is_some()));
+        }
+    }
+
+    #[test]
+    fn test_partition_point() {
+        // [0)[1)[2)...[99)
+        let mut segment_map = SegmentMap::new();
+        for i in 0..100 {
+            segment_map.insert(Segment::new(3 * i, 3 * i + 2), i);
+        }
+
+        assert_eq!(Some((&Segment::new(0, 2), &0)), segment_map.partition_point(|_| false));
+        assert_eq!(None, segment_map.partition_point(|_| true));
+        assert_eq!(Some((&Segment::new(30, 32), &10)), segment_map.partition_point(|segment| segment.upper() < &31));
+        assert_eq!(Some((&Segment::new(33, 35), &11)), segment_map.partition_point(|segment| segment.upper() <= &32));
+        assert_eq!(None, SegmentMap::<u32, u32>::new().partition_point(|_| false));
+    }
+
+    #[test]
+    fn test_insert_overwrite() {
+        // [0----------)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+
+        // [0-|3--)
+        assert_eq!(vec![(Segment::new(3, 6), 0)], segment_map.insert_overwrite(Segment::new(3, 6), 3));
+        assert_eq!(vec![
+            (&Segment::new(0, 3), &0),
+            (&Segment::new(3, 6), &3),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // [0-|3--|)--[1----)
+        segment_map.insert(Segment::new(6, 6), 2);
+        segment_map.insert(Segment::new(9, 15), 1);
+        assert!(segment_map.insert_overwrite(Segment::new(6, 9), 4).is_empty());
+
+        // [5-------------)
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 6), 3),
+            (Segment::new(6, 6), 2),
+            (Segment::new(6, 9), 4),
+            (Segment::new(9, 12), 1),
+        ], segment_map.insert_overwrite(Segment::new(0, 12), 5));
+        assert_eq!(vec![
+            (&Segment::new(0, 12), &5),
+            (&Segment::new(12, 15), &1),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // [5-|)[5--------)
+        segment_map.insert(Segment::new(3, 3), 6);
+        segment_map.insert_overwrite(Segment::new(3, 3), 7);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 5),
+            (Segment::new(3, 3), 7),
+            (Segment::new(3, 12), 5),
+        ], segment_map.insert_overwrite(Segment::new(0, 12), 8));
+    }
+
+    #[test]
+    fn test_split_off() {
+        // [0----)---[1----|2----)|
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(9, 15), 1);
+        segment_map.insert(Segment::new(15, 21), 2);
+        segment_map.insert(Segment::new(21, 21), 3);
+
+        // [0----)---[1-)        [1-|2----)|
+        let right = segment_map.split_off(&12);
+        assert_eq!(vec![
+            (&Segment::new(0, 6), &0),
+            (&Segment::new(9, 12), &1),
+        ], segment_map.iter().collect::<Vec<_>>());
+        assert_eq!(vec![
+            (&Segment::new(12, 15), &1),
+            (&Segment::new(15, 21), &2),
+            (&Segment::new(21, 21), &3),
+        ], right.iter().collect::<Vec<_>>());
+        assert_eq!(2, segment_map.len());
+        assert_eq!(3, right.len());
+
+        // splitting at a boundary or outside the span moves whole segments
+        assert_eq!(1, segment_map.split_off(&6).len());
+        assert_eq!(1, segment_map.len());
+        assert!(segment_map.split_off(&100).is_empty());
+        assert_eq!(1, segment_map.split_off(&-1).len());
+        assert!(segment_map.is_empty());
+    }
+
+    #[test]
+    fn test_append() {
+        // [0----)---[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(9, 15), 1);
+
+        // ---[2----)---|------[3----)
+        let mut other = SegmentMap::new();
+        other.insert(Segment::new(3, 9), 2);
+        other.insert(Segment::new(13, 13), 4);
+        other.insert(Segment::new(18, 24), 3);
+
+        // [0-|2----|1--|-)---[3----)
+        segment_map.append(&mut other);
+        assert!(other.is_empty());
+        assert_eq!(vec![
+            (&Segment::new(0, 3), &0),
+            (&Segment::new(3, 9), &2),
+            (&Segment::new(9, 13), &1),
+            (&Segment::new(13, 13), &4),
+            (&Segment::new(13, 15), &1),
+            (&Segment::new(18, 24), &3),
+        ], segment_map.iter().collect::<Vec<_>>());
+        assert_eq!(6, segment_map.len());
+
+        // split_off and append are inverses, up to the split at 12
+        let expected = segment_map.clone();
+        let mut right = segment_map.split_off(&12);
+        segment_map.append(&mut right);
+        assert_eq!(7, segment_map.len());
+        for key in 0..24 {
+            assert_eq!(expected.get(&key), segment_map.get(&key));
+        }
+
+        let mut empty = SegmentMap::new();
+        empty.append(&mut segment_map);
+        assert!(segment_map.is_empty());
+        assert_eq!(7, empty.len());
+    }
+
+    #[test]
+    fn test_union_with() {
+        // [1----------)---[2----)---[4-)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 12), 1);
+        segment_map.insert(Segment::new(15, 21), 2);
+        segment_map.insert(Segment::new(24, 27), 4);
+        segment_map.insert(Segment::new(22, 22), 8);
+
+        // ------[10---------)-|[20-)
+        let mut other = SegmentMap::new();
+        other.insert(Segment::new(6, 18), 10);
+        other.insert(Segment::new(19, 19), 80);
+        other.insert(Segment::new(21, 24), 20);
+
+        // [1----|11--|10|12-|2-|20|4-)
+        let union = segment_map.union_with(&other, |left, right| left.unwrap_or(&0) + right.unwrap_or(&0));
+        assert_eq!(vec![
+            (&Segment::new(0, 6), &1),
+            (&Segment::new(6, 12), &11),
+            (&Segment::new(12, 15), &10),
+            (&Segment::new(15, 18), &12),
+            (&Segment::new(18, 21), &2),
+            (&Segment::new(21, 24), &20),
+            (&Segment::new(24, 27), &4),
+        ], union.iter().collect::<Vec<_>>());
+        assert_eq!(7, union.len());
+
+        assert_eq!(segment_map.iter().filter(|(segment, _)| !segment.is_empty()).collect::<Vec<_>>(), segment_map.union_with(&SegmentMap::new(), |left, _| *left.unwrap()).iter().collect::<Vec<_>>());
+        assert!(SegmentMap::<i32, i32>::new().union_with(&SegmentMap::new(), |_, _| 0).is_empty());
+
+        // compare against per-key lookups
+        let mut seed = 13u64;
+        let mut random = |bound: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        for _ in 0..64 {
+            let (mut left, mut right) = (SegmentMap::new(), SegmentMap::new());
+            for i in 0..8 {
+                let lower = random(40);
+                left.insert(Segment::new(lower, lower + random(8)), i);
+                let lower = random(40);
+                right.insert(Segment::new(lower, lower + random(8)), 10 * i);
+            }
+            let combine = |left: Option<&u64>, right: Option<&u64>| 100 * left.map_or(0, |left| left + 1) + right.map_or(0, |right| right + 1);
+            let union = left.union_with(&right, combine);
+            for key in 0..48 {
+                let expected = Some(combine(left.get(&key), right.get(&key))).filter(|expected| *expected != 0);
+                assert_eq!(expected.as_ref(), union.get(&key));
+            }
+            assert!(union.segments().all(|segment| !segment.is_empty()));
+        }
+    }
+
+    #[test]
+    fn test_intersect_with() {
+        // [a----------)
+        // ------[b----------)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 12), 'a');
+        let mut other = SegmentMap::new();
+        other.insert(Segment::new(6, 18), 'b');
+
+        // ------[b----)
+        let intersection = segment_map.intersect_with(&other, |_, right| *right);
+        assert_eq!(vec![(&Segment::new(6, 12), &'b')], intersection.iter().collect::<Vec<_>>());
+
+        // [a----------)---[c----)
+        // ------[b----------)---|[d----)
+        segment_map.insert(Segment::new(15, 21), 'c');
+        other.insert(Segment::new(21, 21), 'e');
+        other.insert(Segment::new(21, 27), 'd');
+        let intersection = segment_map.intersect_with(&other, |left, right| if left < right { *right } else { *left });
+        assert_eq!(vec![
+            (&Segment::new(6, 12), &'b'),
+            (&Segment::new(15, 18), &'c'),
+        ], intersection.iter().collect::<Vec<_>>());
+
+        // touching segments do not intersect
+        let mut other = SegmentMap::new();
+        other.insert(Segment::new(12, 15), 'b');
+        assert!(segment_map.intersect_with(&other, |left, _| *left).is_empty());
+    }
+
+    #[test]
+    fn test_drain() {
+        // [0----|1----)---|---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(18, 24), 2);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(15, 15), 3);
+        segment_map.insert(Segment::new(6, 12), 1);
+        let expected = segment_map.clone().into_iter().collect::<Vec<_>>();
+
+        assert_eq!(expected, segment_map.drain().collect::<Vec<_>>());
+        assert!(segment_map.is_empty());
+        assert_eq!(0, segment_map.drain().count());
+    }
+
+    #[test]
+    fn test_extract_if() {
+        // [0----|1----)---|---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(18, 24), 2);
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(15, 15), 3);
+        segment_map.insert(Segment::new(6, 12), 1);
+
+        // [0----)---------[2----)
+        assert_eq!(vec![
+            (Segment::new(6, 12), 1),
+            (Segment::new(15, 15), 3),
+        ], segment_map.extract_if(|_, value| value % 2 == 1).collect::<Vec<_>>());
+        assert_eq!(vec![
+            (&Segment::new(0, 6), &0),
+            (&Segment::new(18, 24), &2),
+        ], segment_map.iter().collect::<Vec<_>>());
+        assert_eq!(2, segment_map.len());
+
+        // removed even if the iterator is dropped
+        drop(segment_map.extract_if(|segment, _| segment.lower() > &6));
+        assert_eq!(vec![(&Segment::new(0, 6), &0)], segment_map.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_extract() {
+        // [0----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+
+        // [0)--[0)
+        assert_eq!(vec![(Segment::new(2, 4), 0)], segment_map.extract(&Segment::new(2, 4)).collect::<Vec<_>>());
+        assert_eq!(vec![
+            (&Segment::new(0, 2), &0),
+            (&Segment::new(4, 6), &0),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // [0)--[0)--[1--)
+        segment_map.insert(Segment::new(8, 12), 1);
+        assert_eq!(vec![
+            (Segment::new(5, 6), 0),
+            (Segment::new(8, 10), 1),
+        ], segment_map.extract(&Segment::new(5, 10)).collect::<Vec<_>>());
+        assert_eq!(vec![
+            (&Segment::new(0, 2), &0),
+            (&Segment::new(4, 5), &0),
+            (&Segment::new(10, 12), &1),
+        ], segment_map.iter().collect::<Vec<_>>());
+        assert_eq!(0, segment_map.extract(&Segment::new(6, 8)).count());
+        assert_eq!(3, segment_map.len());
+    }
+
+    #[test]
+    fn test_map_values() {
+        // [0)[1)[2)[3)[4)
+        let mut segment_map = SegmentMap::new();
+        for i in 0..5 {
+            segment_map.insert(Segment::new(2 * i, 2 * i + 2), i);
+        }
+
+        // values are visited in ascending order
+        let mut visited = Vec::new();
+        let mapped = segment_map.map_values_ref(|value| {
+            visited.push(*value);
+            format!("{}", value)
+        });
+        assert_eq!(vec![0, 1, 2, 3, 4], visited);
+        assert_eq!(segment_map.root.as_ref().map(|root| root.height), mapped.root.as_ref().map(|root| root.height));
+        assert_eq!(5, mapped.len());
+        assert_eq!(Some(&String::from("3")), mapped.get(&7));
+
+        // [0)[2)[4)[6)[8)
+        let mapped = segment_map.map_values(|value| 2 * value);
+        assert_eq!(vec![0, 2, 4, 6, 8], mapped.values().cloned().collect::<Vec<_>>());
+        assert!(mapped.root.as_ref().and_then(|root| root.checked_height()).is_some());
+    }
+
+    #[test]
+    fn test_clone_from() {
+        let node = |segment_map: &SegmentMap<u32, u32>| segment_map.root.as_ref().map(|root| root as *const SegmentMapNode<u32, u32>);
+        let left = |segment_map: &SegmentMap<u32, u32>| segment_map.root.as_ref().and_then(|root| root.left.as_ref().as_ref()).map(|left| left as *const SegmentMapNode<u32, u32>);
+
+        // [0)[1)[2)
+        let mut source = SegmentMap::new();
+        for i in 0..3 {
+            source.insert(Segment::new(2 * i, 2 * i + 2), i);
+        }
+
+        // [5)[6)[7), same shape
+        let mut segment_map = SegmentMap::new();
+        for i in 0..3 {
+            segment_map.insert(Segment::new(10 * i, 10 * i + 5), i + 5);
+        }
+        let before = (node(&segment_map), left(&segment_map));
+        segment_map.clone_from(&source);
+        assert_eq!(source, segment_map);
+        assert_eq!(before, (node(&segment_map), left(&segment_map)));
+
+        // [5), different shape
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 5), 5);
+        segment_map.clone_from(&source);
+        assert_eq!(source, segment_map);
+        assert_eq!(3, segment_map.len());
+        assert!(segment_map.root.as_ref().and_then(|root| root.checked_height()).is_some());
+
+        // empty source
+        segment_map.clone_from(&SegmentMap::new());
+        assert!(segment_map.is_empty());
+        assert_eq!(None, segment_map.iter().next());
+    }
+
+    #[test]
+    fn test_index() {
+        // [a----)---[b----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 'a');
+        segment_map.insert(Segment::new(9, 15), 'b');
+
+        assert_eq!('a', segment_map[&0]);
+        assert_eq!('a', segment_map[&5]);
+        assert_eq!('b', segment_map[&9]);
+    }
+
+    #[test]
+    #[should_panic(expected = "no segment contains key")]
+    fn test_index_uncovered() {
+        // [a----)---[b----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 'a');
+        segment_map.insert(Segment::new(9, 15), 'b');
+
+        let _ = segment_map[&6];
+    }
+
+    #[test]
+    fn test_macro() {
+        let segment_map: SegmentMap<u32, char> = segment_map!{};
+        assert!(segment_map.is_empty());
+
+        // [a----)---[b----)
+        let segment_map = segment_map!{ Segment::new(0, 6) => 'a', Segment::new(9, 15) => 'b' };
+        assert_eq!(vec![
+            (&Segment::new(0, 6), &'a'),
+            (&Segment::new(9, 15), &'b'),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // trailing comma
+        let trailing = segment_map!{
+            Segment::new(0, 6) => 'a',
+            Segment::new(9, 15) => 'b',
+        };
+        assert_eq!(segment_map, trailing);
+
+        // with capacity
+        let with_capacity = segment_map!{ Segment::new(0, 6) => 'a', Segment::new(9, 15) => 'b'; 8 };
+        assert_eq!(segment_map, with_capacity);
+        assert_eq!(8, with_capacity.capacity());
+        let empty: SegmentMap<u32, char> = segment_map!{ ; 4 };
+        assert!(empty.is_empty());
+        assert_eq!(4, empty.capacity());
+    }
+
+    #[test]
+    fn test_get_borrowed() {
+        // [a----)[b----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(String::from("a"), String::from("g")), 'a');
+        segment_map.insert(Segment::new(String::from("g"), String::from("m")), 'b');
+
+        assert_eq!(Some(&'a'), segment_map.get("c"));
+        assert_eq!(Some((&Segment::new(String::from("g"), String::from("m")), &'b')), segment_map.get_entry("g"));
+        assert!(segment_map.contains_key("apple"));
+        assert!(!segment_map.contains_key("m"));
+        assert_eq!('b', segment_map["hello"]);
+
+        assert_eq!(None, segment_map.get_mut("z"));
+        *segment_map.get_mut("b").unwrap() = 'c';
+        assert_eq!(Some(&'c'), segment_map.get("a"));
+    }
+
+    #[test]
+    fn test_cursor() {
+        // [0----)---[1----)[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(9, 15), 1);
+        segment_map.insert(Segment::new(15, 21), 2);
+
+        // [0----)--|-[1----)[2----)
+        let mut cursor = segment_map.lower_bound(&7);
+        assert_eq!(Some((&Segment::new(0, 6), &0)), cursor.peek_prev());
+        assert_eq!(Some((&Segment::new(9, 15), &1)), cursor.peek_next());
+        assert_eq!(Some((&Segment::new(9, 15), &1)), cursor.move_next());
+        assert_eq!(Some((&Segment::new(15, 21), &2)), cursor.move_next());
+        assert_eq!(None, cursor.peek_next());
+        assert_eq!(None, cursor.move_next());
+        assert_eq!(Some((&Segment::new(15, 21), &2)), cursor.peek_prev());
+
+        // |[0----)---[1----)[2----)
+        assert_eq!(Some((&Segment::new(15, 21), &2)), cursor.move_prev());
+        assert_eq!(Some((&Segment::new(9, 15), &1)), cursor.move_prev());
+        assert_eq!(Some((&Segment::new(0, 6), &0)), cursor.move_prev());
+        assert_eq!(None, cursor.move_prev());
+        assert_eq!(Some((&Segment::new(0, 6), &0)), cursor.peek_next());
+
+        // [0----)---|[1----)[2----) and [0----)---[1----)|[2----)
+        assert_eq!(Some((&Segment::new(9, 15), &1)), segment_map.lower_bound(&9).peek_next());
+        assert_eq!(Some((&Segment::new(9, 15), &1)), segment_map.upper_bound(&9).peek_prev());
+        assert_eq!(Some((&Segment::new(9, 15), &1)), segment_map.lower_bound(&12).peek_prev());
+        assert_eq!(Some((&Segment::new(15, 21), &2)), segment_map.upper_bound(&12).peek_next());
+
+        // empty segments sharing a lower bound are visited in order
+        segment_map.insert(Segment::new(9, 9), 3);
+        let mut cursor = segment_map.lower_bound(&9);
+        assert_eq!(Some((&Segment::new(9, 9), &3)), cursor.move_next());
+        assert_eq!(Some((&Segment::new(9, 15), &1)), cursor.move_next());
+        assert_eq!(Some((&Segment::new(9, 15), &1)), cursor.move_prev());
+        assert_eq!(Some((&Segment::new(9, 9), &3)), cursor.move_prev());
+
+        assert_eq!(None, SegmentMap::<u32, u32>::new().lower_bound(&0).peek_next());
+    }
+
+    #[test]
+    fn test_values_mut_order() {
+        // inserted out of order, so tree order and insertion order differ
+        let mut segment_map = SegmentMap::new();
+        for i in [3, 0, 4, 1, 2].iter() {
+            segment_map.insert(Segment::new(2 * i, 2 * i + 1), 1);
+        }
+
+        // [1)-[2)-[3)-[4)-[5)
+        let mut sum = 0;
+        for value in segment_map.values_mut() {
+            sum += *value;
+            *value = sum;
+        }
+        assert_eq!(vec![1, 2, 3, 4, 5], segment_map.values().cloned().collect::<Vec<_>>());
+
+        // [5)-[4)-[3)-[2)-[1)
+        let mut rank = 0;
+        for value in segment_map.values_mut().rev() {
+            rank += 1;
+            *value = rank;
+        }
+        assert_eq!(vec![5, 4, 3, 2, 1], segment_map.values().cloned().collect::<Vec<_>>());
+        assert_eq!(5, segment_map.values_mut().len());
+    }
+
+    #[test]
+    fn test_pretty_tree() {
+        let mut segment_map = SegmentMap::new();
+        assert_eq!("", segment_map.pretty_tree());
+
+        //      [1----)
+        //       /   \
+        // [0----) [2----)
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(12, 18), 2);
+        assert_eq!(format!("{}\n{}\n{}\n",
+            "         [6, 12) 1",
+            "        /         \\",
+            "[0, 6) 0           [12, 18) 2"
+        ), segment_map.pretty_tree());
+
+        //      [1----)
+        //       /   \
+        // [0----) [2----)
+        //            \
+        //           [3----)
+        segment_map.insert(Segment::new(18, 24), 3);
+        assert_eq!(format!("{}\n{}\n{}\n{}\n{}\n",
+            "         [6, 12) 1",
+            "        /         \\",
+            "[0, 6) 0           [12, 18) 2",
+            "                             \\",
+            "                              [18, 24) 3"
+        ), segment_map.pretty_tree());
+    }
+
+    #[test]
+    fn test_unbounded() {
+        // <a----)-----[b---->
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::less_than(5), 'a');
+        segment_map.insert(Segment::at_least(10), 'b');
+        assert_eq!(Some(&'a'), segment_map.get(&i32::MIN));
+        assert_eq!(Some(&'a'), segment_map.get(&4));
+        assert_eq!(None, segment_map.get(&5));
+        assert_eq!(Some(&'b'), segment_map.get(&10));
+        assert_eq!(Some(&'b'), segment_map.get(&(i32::MAX - 1)));
+        assert_eq!(None, segment_map.get(&i32::MAX));
+        assert_eq!(Some(Segment::new(i32::MIN, i32::MAX)), segment_map.span_cloned());
+
+        // <a)--[a)-----[b---->
+        segment_map.remove(&Segment::new(-3, 0));
+        assert_eq!(vec![
+            (&Segment::new(i32::MIN, -3), &'a'),
+            (&Segment::new(0, 5), &'a'),
+            (&Segment::new(10, i32::MAX), &'b'),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        // <a----)-----[b---->
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::less_than(5.0), 'a');
+        segment_map.insert(Segment::at_least(10.0), 'b');
+        assert_eq!(Some(&'a'), segment_map.get(&f64::NEG_INFINITY));
+        assert_eq!(Some(&'a'), segment_map.get(&-1e300));
+        assert_eq!(Some(&'b'), segment_map.get(&f64::MAX));
+    }
+
+    #[test]
+    fn test_vec() {
+        // [0----)---[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(9, 15), 1);
+        segment_map.insert(Segment::new(0, 6), 0);
+        let entries = segment_map.clone().into_vec();
+        assert_eq!(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(9, 15), 1),
+        ], entries);
+        assert_eq!(segment_map, SegmentMap::from(entries));
+
+        // [0-)[2-)[1-)
+        let segment_map = SegmentMap::from(vec![
+            (Segment::new(0, 6), 0),
+            (Segment::new(6, 12), 1),
+            (Segment::new(3, 9), 2),
+        ]);
+        assert_eq!(vec![
+            (Segment::new(0, 3), 0),
+            (Segment::new(3, 9), 2),
+            (Segment::new(9, 12), 1),
+        ], segment_map.into_vec());
+    }
+
+    #[test]
+    fn test_double_ended() {
+        // [0|1|2|3|4|5|6|7|8|9)
+        let mut segment_map = SegmentMap::new();
+        for i in [3, 7, 1, 5, 9, 0, 2, 4, 6, 8].iter() {
+            segment_map.insert(Segment::new(*i, i + 1), *i);
+        }
+        let expected = (0..10).map(|i| (Segment::new(i, i + 1), i)).collect::<Vec<_>>();
+
+        assert_eq!(expected.iter().rev().map(|(segment, value)| (segment, value)).collect::<Vec<_>>(), segment_map.iter().rev().collect::<Vec<_>>());
+
+        // alternating ends meet in the middle exactly once
+        for take_front in 0..=10 {
+            let mut iter = segment_map.iter();
+            let mut front = iter.by_ref().take(take_front).map(|(segment, value)| (*segment, *value)).collect::<Vec<_>>();
+            let back = iter.rev().map(|(segment, value)| (*segment, *value)).collect::<Vec<_>>();
+            front.extend(back.into_iter().rev());
+            assert_eq!(expected, front);
+        }
+
+        let mut iter = segment_map.clone().into_iter();
+        let mut entries = Vec::new();
+        while let (Some(front), back) = (iter.next(), iter.next_back()) {
+            entries.push(front);
+            entries.extend(back);
+        }
+        entries.sort();
+        assert_eq!(expected, entries);
+
+        // [9|8|7|6|5|4|3|2|1|0)
+        let mut iter = segment_map.iter_mut();
+        while let (Some((_, front)), Some((_, back))) = (iter.next(), iter.next_back()) {
+            std::mem::swap(front, back);
+        }
+        assert_eq!((0..10).map(|i| (Segment::new(i, i + 1), 9 - i)).collect::<Vec<_>>(), segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_size_hint() {
+        // [0----|1----)---[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        segment_map.insert(Segment::new(15, 21), 2);
+
+        let mut iter = segment_map.iter();
+        assert_eq!((3, Some(3)), iter.size_hint());
+        iter.next();
+        iter.next_back();
+        assert_eq!(1, iter.len());
+        iter.next();
+        assert_eq!((0, Some(0)), iter.size_hint());
+        assert_eq!(3, segment_map.iter_mut().len());
+        assert_eq!(3, segment_map.values().size_hint().0);
+        assert_eq!(3, segment_map.into_iter().len());
+    }
+
+    #[test]
+    fn test_insert_coalesce() {
+        // [3----------)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert_coalesce(Segment::new(0, 6), 3);
+        segment_map.insert_coalesce(Segment::new(6, 12), 3);
+        assert_eq!(vec![(Segment::new(0, 12), 3)], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        // [3----------)---[4----|3--)
+        segment_map.insert_coalesce(Segment::new(15, 21), 4);
+        segment_map.insert_coalesce(Segment::new(21, 24), 3);
+        assert_eq!(vec![
+            (Segment::new(0, 12), 3),
+            (Segment::new(15, 21), 4),
+            (Segment::new(21, 24), 3),
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        // [3-----------------------)
+        segment_map.insert_coalesce(Segment::new(12, 21), 3);
+        assert_eq!(vec![(Segment::new(0, 24), 3)], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        // [3-------|5--------------)
+        segment_map.insert_coalesce(Segment::new(9, 24), 5);
+        assert_eq!(vec![
+            (Segment::new(0, 9), 3),
+            (Segment::new(9, 24), 5),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_extend_coalescing() {
+        // [1----------|2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.extend_coalescing(vec![
+            (Segment::new(0, 3), 1),
+            (Segment::new(3, 6), 1),
+            (Segment::new(6, 12), 1),
+            (Segment::new(12, 18), 2),
+        ]);
+        assert_eq!(vec![
+            (Segment::new(0, 12), 1),
+            (Segment::new(12, 18), 2),
+        ], segment_map.clone().into_iter().collect::<Vec<_>>());
+
+        // [1----------|2----)---[2----)
+        //                   [2-)
+        // [1----------|2-------------)
+        segment_map.extend_coalescing(vec![
+            (Segment::new(21, 27), 2),
+            (Segment::new(18, 21), 2),
+        ]);
+        assert_eq!(vec![
+            (Segment::new(0, 12), 1),
+            (Segment::new(12, 27), 2),
+        ], segment_map.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_gaps() {
+        // [0----)-----[1----|2----)--[3-)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(9, 9), 4);
+        segment_map.insert(Segment::new(12, 18), 1);
+        segment_map.insert(Segment::new(18, 24), 2);
+        segment_map.insert(Segment::new(27, 30), 3);
+        assert_eq!(vec![
+            Segment::new(6, 12),
+            Segment::new(24, 27),
+        ], segment_map.gaps().collect::<Vec<_>>());
+
+        assert_eq!(0, SegmentMap::<i32, i32>::new().gaps().count());
+    }
+
+    #[test]
+    fn test_gaps_within() {
+        // ------[0----)------
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(6, 12), 0);
+        assert_eq!(vec![
+            Segment::new(0, 6),
+            Segment::new(12, 18),
+        ], segment_map.gaps_within(&Segment::new(0, 18)).collect::<Vec<_>>());
+
+        // ------[0----)---[1----)---
+        segment_map.insert(Segment::new(15, 21), 1);
+        assert_eq!(vec![Segment::new(12, 15)], segment_map.gaps_within(&Segment::new(9, 18)).collect::<Vec<_>>());
+        assert_eq!(vec![
+            Segment::new(3, 6),
+            Segment::new(12, 15),
+            Segment::new(21, 24),
+        ], segment_map.gaps_within(&Segment::new(3, 24)).collect::<Vec<_>>());
+        assert_eq!(vec![Segment::new(0, 3)], segment_map.gaps_within(&Segment::new(0, 3)).collect::<Vec<_>>());
+        assert_eq!(0, segment_map.gaps_within(&Segment::new(7, 11)).count());
+        assert_eq!(0, segment_map.gaps_within(&Segment::new(13, 13)).count());
+    }
+
+    #[test]
+    fn test_contains_segment() {
+        // [0----)[1----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(6, 12), 1);
+        assert!(segment_map.contains_segment(&Segment::new(2, 10)));
+        assert!(segment_map.contains_segment(&Segment::new(0, 12)));
+        assert!(!segment_map.contains_segment(&Segment::new(0, 13)));
+
+        // [0----)[1-)--
+        segment_map.remove(&Segment::new(9, 12));
+        assert!(segment_map.contains_segment(&Segment::new(2, 9)));
+        assert!(!segment_map.contains_segment(&Segment::new(2, 10)));
+
+        // [0-)--[1-)--
+        segment_map.remove(&Segment::new(3, 6));
+        assert!(!segment_map.contains_segment(&Segment::new(2, 8)));
+        assert!(segment_map.contains_segment(&Segment::new(4, 4)));
+    }
+
+    #[test]
+    fn test_span_cloned() {
+        let mut segment_map = SegmentMap::new();
+        assert_eq!(None, segment_map.span_cloned());
+
+        // ---[0----)---[1----)
+        segment_map.insert(Segment::new(3, 9), 0);
+        segment_map.insert(Segment::new(12, 18), 1);
+        let span = segment_map.span_cloned();
+        segment_map.clear();
+        assert_eq!(Some(Segment::new(3, 18)), span);
+    }
+
+    #[test]
+    fn test_around() {
+        // [0----)---[1----)[2----)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0.0, 6.0), 0);
+        segment_map.insert(Segment::new(9.0, 15.0), 1);
+        segment_map.insert(Segment::new(15.0, 21.0), 2);
+
+        // ----------------[-)------
+        assert_eq!(vec![
+            (&Segment::new(9.0, 15.0), &1),
+            (&Segment::new(15.0, 21.0), &2),
+        ], segment_map.around(&15.0, 1e-9).collect::<Vec<_>>());
+
+        // ------[--)---------------
+        assert_eq!(0, segment_map.around(&7.5, 1.5).count());
+        assert_eq!(vec![
+            (&Segment::new(0.0, 6.0), &0),
+            (&Segment::new(9.0, 15.0), &1),
+        ], segment_map.around(&7.5, 1.6).collect::<Vec<_>>());
+
+        // a zero radius is a point, a negative one is empty
+        assert_eq!(vec![(&Segment::new(15.0, 21.0), &2)], segment_map.around(&15.0, 0.0).collect::<Vec<_>>());
+        assert_eq!(0, segment_map.around(&12.0, -1.0).count());
+
+        // [0-)-----------------[1-)
+        let mut segment_map = SegmentMap::new();
+        segment_map.insert(Segment::new(0u32, 2), 0);
+        segment_map.insert(Segment::new(u32::MAX - 2, u32::MAX), 1);
+
+        // the window is clipped at both ends of the key range
+        assert_eq!(vec![(&Segment::new(0, 2), &0)], segment_map.around(&1, 2).collect::<Vec<_>>());
+        assert_eq!(vec![(&Segment::new(u32::MAX - 2, u32::MAX), &1)], segment_map.around(&(u32::MAX - 1), 2).collect::<Vec<_>>());
+        assert_eq!(2, segment_map.around(&(u32::MAX / 2), u32::MAX).count());
+    }
+
+    #[test]
+    fn test_total_covered() {
+        let mut segment_map = SegmentMap::new();
+        assert_eq!(0u32, segment_map.total_covered());
+
+        // [0----)------[1----)
+        segment_map.insert(Segment::new(0, 6), 0);
+        segment_map.insert(Segment::new(12, 18), 1);
+        assert_eq!(12u32, segment_map.total_covered());
+
+        // [0----)--|---[1----)
+        segment_map.insert(Segment::new(9, 9), 2);
+        assert_eq!(12u32, segment_map.total_covered());
+    }
+
+    #[test]
+    fn test_eq() {
+        // [0----)---[1----)---[2----)
+        let mut ascending = SegmentMap::new();
+        ascending.insert(Segment::new(0, 6), 0);
+        ascending.insert(Segment::new(9, 15), 1);
+        ascending.insert(Segment::new(18, 24), 2);
+
+        let mut balanced = SegmentMap::new();
+        balanced.insert(Segment::new(9, 15), 1);
+        balanced.insert(Segment::new(0, 6), 0);
+        balanced.insert(Segment::new(18, 24), 2);
+        assert_eq!(ascending, balanced);
+
+        // [0----)---[1----)---[3----)
+        balanced.insert(Segment::new(18, 24), 3);
+        assert_ne!(ascending, balanced);
+
+        // [0----)---[1----)
+        balanced.remove(&Segment::new(18, 24));
+        assert_ne!(ascending, balanced);
+    }
+
+    #[test]
+    fn test_hash() {
+        // [0----)---[1----)---[2----)
+        let mut ascending = SegmentMap::new();
+        ascending.insert(Segment::new(0, 6), 0);
+        ascending.insert(Segment::new(9, 15), 1);
+        ascending.insert(Segment::new(18, 24), 2);
+
+        let mut balanced = SegmentMap::new();
+        balanced.insert(Segment::new(9, 15), 1);
+        balanced.insert(Segment::new(0, 6), 0);
+        balanced.insert(Segment::new(18, 24), 2);
+
+        let mut set = HashSet::new();
+        set.insert(ascending);
+        assert!(set.contains(&balanced));
+    }
+
+    #[test]
+    fn test_cmp() {
+        // [0----)---[1----)---[2----)
+        let mut ascending = SegmentMap::new();
+        ascending.insert(Segment::new(0, 6), 0);
+        ascending.insert(Segment::new(9, 15), 1);
+        ascending.insert(Segment::new(18, 24), 2);
+
+        let mut balanced = SegmentMap::new();
+        balanced.insert(Segment::new(9, 15), 1);
+        balanced.insert(Segment::new(0, 6), 0);
+        balanced.insert(Segment::new(18, 24), 2);
+        assert_eq!(Ordering::Equal, ascending.cmp(&balanced));
+
+        // [0----)---[1----)
+        let mut prefix = balanced.clone();
+        prefix.remove(&Segment::new(18, 24));
+        assert!(prefix < ascending);
+
+        // [0----)---[1----)---[3----)
+        balanced.insert(Segment::new(18, 24), 3);
+        assert!(ascending < balanced);
+
+        // [0----)---[1------)
+        let mut longer = prefix.clone();
+        longer.insert(Segment::new(9, 16), 1);
+        assert!(prefix < longer);
+        assert!(ascending < longer);
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut segment_map = SegmentMap::with_capacity(8);
+        assert_eq!(8, segment_map.capacity());
+        assert!(segment_map.is_empty());
+
+        // [0-|1-|2-|3-|4-|5-|6-|7-)
+        for value in 0..8 {
+            segment_map.insert(Segment::new(value * 3, value * 3 + 3), value);
+        }
+        assert_eq!(8, segment_map.capacity());
+        assert_eq!(Some(&5), segment_map.get(&16));
+
+        // [0-|1-|2-|3-|4-|5-|6-|7-|8-)
+        segment_map.insert(Segment::new(24, 27), 8);
+        assert_eq!(9, segment_map.capacity());
+        assert_eq!((0..9).collect::<Vec<_>>(), segment_map.values().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_clear() {
+        // [0-|1-|2-|3-|4-|5-|6-|7-)
+        let mut segment_map = SegmentMap::new();
+        for value in 0..8 {
+            segment_map.insert(Segment::new(value * 3, value * 3 + 3), value);
+        }
+        segment_map.clear();
+        assert!(segment_map.is_empty());
+        assert_eq!(None, segment_map.get(&4));
+        assert_eq!(8, segment_map.capacity());
+
+        // ----[0-)----[1-)----[2-)----[3-)
+        for value in 0..4 {
+            segment_map.insert(Segment::new(value * 6 + 4, value * 6 + 6), value);
+        }
+        assert_eq!(8, segment_map.capacity());
+        assert_eq!(vec![
+            (&Segment::new(4, 6), &0),
+            (&Segment::new(10, 12), &1),
+            (&Segment::new(16, 18), &2),
+            (&Segment::new(22, 24), &3),
+        ], segment_map.iter().collect::<Vec<_>>());
+
+        segment_map.shrink_to_fit();
+        assert_eq!(4, segment_map.capacity());
+        segment_map.clear();
+        segment_map.shrink_to_fit();
+        assert_eq!(0, segment_map.capacity());
     }
 
     #[test]
@@ -638,12 +5283,14 @@ mod tests {
                     segment_map.insert(insert_segment, insert_value);
                 }
                 segment_map.remove(&remove_segment);
+                assert_eq!(expected_segments.len(), segment_map.len(), "\npermutation:\n\n{}\ncase:\n\n{}\n", permutation_description, case_description);
                 assert_eq!(expected_segments, segment_map.into_iter().collect::<Vec<_>>(), "\npermutation:\n\n{}\ncase:\n\n{}\n", permutation_description, case_description);
             }
         }
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_update() {
         let permutations = vec![
             vec![
@@ -1395,12 +6042,14 @@ mod tests {
                 }
                 let (update_segment, update_value) = update_segment;
                 segment_map.update(&update_segment, |_| Some(update_value));
+                assert_eq!(expected_segments.len(), segment_map.len(), "\npermutation:\n\n{}\ncase:\n\n{}\n", permutation_description, case_description);
                 assert_eq!(expected_segments, segment_map.into_iter().collect::<Vec<_>>(), "\npermutation:\n\n{}\ncase:\n\n{}\n", permutation_description, case_description);
             }
         }
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_update_remove() {
         let permutations = vec![
             vec![
@@ -2066,6 +6715,7 @@ mod tests {
                     segment_map.insert(insert_segment, insert_value);
                 }
                 segment_map.update(&update_segment, |_| None);
+                assert_eq!(expected_segments.len(), segment_map.len(), "\npermutation:\n\n{}\ncase:\n\n{}\n", permutation_description, case_description);
                 assert_eq!(expected_segments, segment_map.into_iter().collect::<Vec<_>>(), "\npermutation:\n\n{}\ncase:\n\n{}\n", permutation_description, case_description);
             }
         }
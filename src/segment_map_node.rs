@@ -1,11 +1,259 @@
+use alloc::{
+    boxed::Box,
+    format,
+    vec::Vec,
+};
+use core::{
+    borrow::Borrow,
+    fmt::Debug,
+};
 use crate::Segment;
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+// the overlap of connected segments, which (unlike `Segment::intersection`) is an empty segment
+// rather than `None` when they only touch or one is empty
+fn overlap<K>(segment: &Segment<K>, other: &Segment<K>) -> Option<Segment<K>>
+where
+    K: Clone + PartialOrd
+{
+    if segment.is_connected(other) {
+        Some(Segment::new(
+            if segment.lower() < other.lower() { other.lower().clone() } else { segment.lower().clone() },
+            if other.upper() < segment.upper() { other.upper().clone() } else { segment.upper().clone() },
+        ))
+    } else { None }
+}
+
+#[derive(Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct SegmentMapNode<K, V> {
     pub segment: Segment<K>,
     pub value: V,
     pub left: Box<Option<SegmentMapNode<K, V>>>,
-    pub right: Box<Option<SegmentMapNode<K, V>>>
+    pub right: Box<Option<SegmentMapNode<K, V>>>,
+    pub height: usize,
+}
+
+impl<K, V> Clone for SegmentMapNode<K, V>
+where
+    K: Clone,
+    V: Clone,
+{
+    fn clone(&self) -> SegmentMapNode<K, V> {
+        SegmentMapNode {
+            segment: self.segment.clone(),
+            value: self.value.clone(),
+            left: self.left.clone(),
+            right: self.right.clone(),
+            height: self.height,
+        }
+    }
+
+    // clone field by field so that children present on both sides keep their boxes
+    fn clone_from(&mut self, source: &SegmentMapNode<K, V>) {
+        self.segment.clone_from(&source.segment);
+        self.value.clone_from(&source.value);
+        self.left.clone_from(&source.left);
+        self.right.clone_from(&source.right);
+        self.height = source.height;
+    }
+}
+
+// the child links of a node, which hold its heap allocations, so a map can keep them for reuse
+pub type Links<K, V> = (Box<Option<SegmentMapNode<K, V>>>, Box<Option<SegmentMapNode<K, V>>>);
+
+fn height<K, V>(node: &Option<SegmentMapNode<K, V>>) -> usize {
+    node.as_ref().map_or(0, |node| node.height)
+}
+
+fn draw(rows: &mut Vec<Vec<char>>, row: usize, column: usize, chars: &[char]) {
+    if rows.len() <= row {
+        rows.resize(row + 1, Vec::new());
+    }
+    if rows[row].len() < column + chars.len() {
+        rows[row].resize(column + chars.len(), ' ');
+    }
+    rows[row][column..(column + chars.len())].copy_from_slice(chars);
+}
+
+impl<K, V> SegmentMapNode<K, V> {
+    /// Builds a childless node in `links`, which must be empty, rather than allocating new ones.
+    pub fn leaf(segment: Segment<K>, value: V, (left, right): Links<K, V>) -> SegmentMapNode<K, V> {
+        debug_assert!(left.is_none() && right.is_none(), "links must be empty");
+        SegmentMapNode { segment, value, left, right, height: 1 }
+    }
+
+    /// Drops the segments and values of this subtree, keeping the (emptied) links of every node in
+    /// `spare`.
+    pub fn release(self, spare: &mut Vec<Links<K, V>>) {
+        let SegmentMapNode { mut left, mut right, .. } = self;
+        if let Some(child) = left.take() {
+            child.release(spare);
+        }
+        if let Some(child) = right.take() {
+            child.release(spare);
+        }
+        spare.push((left, right));
+    }
+
+    /// Restores the AVL invariant at this node. Both children must already be balanced, but their
+    /// heights may differ by any amount (as after removing a whole range from one side).
+    pub fn balance(mut self) -> SegmentMapNode<K, V> {
+        let (left, right) = (height(&self.left), height(&self.right));
+        // if left is too tall, join self into its right spine
+        if left > right + 1 {
+            self.join_right()
+        // if right is too tall, join self into its left spine
+        } else if right > left + 1 {
+            self.join_left()
+        // otherwise, self is balanced
+        } else {
+            self.update_height();
+            self
+        }
+    }
+
+    /// Recomputes the height of this subtree, returning `None` if a cached height is stale or a
+    /// node is unbalanced.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn checked_height(&self) -> Option<usize> {
+        let left = self.left.as_ref().as_ref().map_or(Some(0), SegmentMapNode::checked_height)?;
+        let right = self.right.as_ref().as_ref().map_or(Some(0), SegmentMapNode::checked_height)?;
+        let height = 1 + core::cmp::max(left, right);
+        if (height == self.height) && (left <= right + 1) && (right <= left + 1) {
+            Some(height)
+        } else { None }
+    }
+
+    fn update_height(&mut self) {
+        self.height = 1 + core::cmp::max(height(&self.left), height(&self.right));
+    }
+
+    fn rotate_left(mut self) -> SegmentMapNode<K, V> {
+        let mut right = self.right.take().expect("rotation requires a right child");
+        *self.right = right.left.take();
+        self.update_height();
+        *right.left = Some(self);
+        right.update_height();
+        right
+    }
+
+    fn rotate_right(mut self) -> SegmentMapNode<K, V> {
+        let mut left = self.left.take().expect("rotation requires a left child");
+        *self.left = left.right.take();
+        self.update_height();
+        *left.right = Some(self);
+        left.update_height();
+        left
+    }
+
+    fn join_right(mut self) -> SegmentMapNode<K, V> {
+        let mut left = self.left.take().expect("join requires a left child");
+        *self.left = left.right.take();
+        // if self is still too short, keep descending the right spine
+        let descend = height(&self.left) > height(&self.right) + 1;
+        let mut joined = if descend { self.join_right() } else {
+            self.update_height();
+            self
+        };
+        // if joined is too tall, rotate it up (twice if it was attached directly)
+        if joined.height > height(&left.left) + 1 {
+            if !descend {
+                joined = joined.rotate_right();
+            }
+            *left.right = Some(joined);
+            left.rotate_left()
+        // otherwise, joined fits as is
+        } else {
+            *left.right = Some(joined);
+            left.update_height();
+            left
+        }
+    }
+
+    fn join_left(mut self) -> SegmentMapNode<K, V> {
+        let mut right = self.right.take().expect("join requires a right child");
+        *self.right = right.left.take();
+        // if self is still too short, keep descending the left spine
+        let descend = height(&self.right) > height(&self.left) + 1;
+        let mut joined = if descend { self.join_left() } else {
+            self.update_height();
+            self
+        };
+        // if joined is too tall, rotate it up (twice if it was attached directly)
+        if joined.height > height(&right.right) + 1 {
+            if !descend {
+                joined = joined.rotate_left();
+            }
+            *right.left = Some(joined);
+            right.rotate_right()
+        // otherwise, joined fits as is
+        } else {
+            *right.left = Some(joined);
+            right.update_height();
+            right
+        }
+    }
+
+    pub fn map_values<W, F>(self, f: &mut F) -> SegmentMapNode<K, W>
+    where
+        F: FnMut(V) -> W
+    {
+        // map left first so values are visited in ascending order
+        let left = (*self.left).map(|left| left.map_values(f));
+        let value = f(self.value);
+        let right = (*self.right).map(|right| right.map_values(f));
+        SegmentMapNode {
+            segment: self.segment,
+            value,
+            left: Box::new(left),
+            right: Box::new(right),
+            height: self.height,
+        }
+    }
+
+    pub fn map_values_ref<W, F>(&self, f: &mut F) -> SegmentMapNode<K, W>
+    where
+        K: Clone,
+        F: FnMut(&V) -> W
+    {
+        // map left first so values are visited in ascending order
+        let left = self.left.as_ref().as_ref().map(|left| left.map_values_ref(f));
+        let value = f(&self.value);
+        let right = self.right.as_ref().as_ref().map(|right| right.map_values_ref(f));
+        SegmentMapNode {
+            segment: self.segment.clone(),
+            value,
+            left: Box::new(left),
+            right: Box::new(right),
+            height: self.height,
+        }
+    }
+}
+
+impl<K, V> SegmentMapNode<K, V>
+where
+    K: Debug,
+    V: Debug,
+{
+    /// Draws this subtree with one row of labels per level, each label in its in-order column,
+    /// and a row of branches below each level.
+    pub fn draw(&self, depth: usize, column: &mut usize, rows: &mut Vec<Vec<char>>) {
+        // draw left first, so labels are laid out in order
+        if let Some(left) = self.left.as_ref() {
+            left.draw(depth + 1, column, rows);
+        }
+        let label = format!("[{:?}, {:?}) {:?}", self.segment.lower(), self.segment.upper(), self.value).chars().collect::<Vec<_>>();
+        let start = *column;
+        *column += label.len() + 1;
+        draw(rows, 2 * depth, start, &label);
+        // branches sit in the gaps just before and just after the label
+        if self.left.is_some() {
+            draw(rows, 2 * depth + 1, start - 1, &['/']);
+        }
+        if let Some(right) = self.right.as_ref() {
+            draw(rows, 2 * depth + 1, start + label.len(), &['\\']);
+            right.draw(depth + 1, column, rows);
+        }
+    }
 }
 
 impl<K, V> SegmentMapNode<K, V> 
@@ -18,6 +266,23 @@ where
             value,
             left: Box::new(left),
             right: Box::new(right),
+            height: 1,
+        }.balance()
+    }
+
+    pub fn from_sorted<I>(entries: &mut I, len: usize) -> Option<SegmentMapNode<K, V>>
+    where
+        I: Iterator<Item = (Segment<K>, V)>
+    {
+        // if there is nothing left, there is no node
+        if len == 0 {
+            None
+        // otherwise, build left half, take middle, build right half
+        } else {
+            let left = SegmentMapNode::from_sorted(entries, len / 2);
+            let (segment, value) = entries.next().expect("fewer entries than len");
+            let right = SegmentMapNode::from_sorted(entries, len - len / 2 - 1);
+            Some(SegmentMapNode::new(segment, value, left, right))
         }
     }
 
@@ -33,14 +298,23 @@ where
         } else { self }
     }
 
+    pub fn min_node_mut(&mut self) -> &mut SegmentMapNode<K, V> {
+        let mut node = self;
+        // descend left as far as possible
+        while node.left.is_some() {
+            node = node.left.as_mut().as_mut().unwrap();
+        }
+        node
+    }
+
     pub fn remove_min_node(mut self) -> (Option<SegmentMapNode<K, V>>, SegmentMapNode<K, V>) {
         // if left exists, recurse
         if let Some(left) = self.left.take() {
             let (left, min_node) = left.remove_min_node();
-            self.left = Box::new(left);
-            (Some(self), min_node)
-        // otherwise, self is minimum
-        } else { (None, self) }
+            *self.left = left;
+            (Some(self.balance()), min_node)
+        // otherwise, self is minimum, right takes its place
+        } else { (self.right.take(), self) }
     }
 
     pub fn max_key(&self) -> &K {
@@ -55,84 +329,367 @@ where
         } else { self }
     }
 
+    pub fn max_node_mut(&mut self) -> &mut SegmentMapNode<K, V> {
+        let mut node = self;
+        // descend right as far as possible
+        while node.right.is_some() {
+            node = node.right.as_mut().as_mut().unwrap();
+        }
+        node
+    }
+
     pub fn span(&self) -> Segment<&K> {
         Segment::new(self.min_key(), self.max_key())
     }
 
-    pub fn get(&self, key: &K) -> Option<&V> {
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
         self.get_entry(key).map(|(_, v)| v)
     }
 
-    pub fn get_entry(&self, key: &K) -> Option<(&Segment<K>, &V)> {
-        // if self segment contains key
-        if self.segment.contains(key) {
+    pub fn get_entry<Q>(&self, key: &Q) -> Option<(&Segment<K>, &V)>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
+        let mut node = self;
+        loop {
+            // if node segment contains key
+            if (node.segment.lower().borrow() <= key) && (key < node.segment.upper().borrow()) {
+                return Some((&node.segment, &node.value));
+            // if key is less than node segment, descend left
+            } else if key < node.segment.lower().borrow() {
+                node = node.left.as_ref().as_ref()?;
+            // otherwise, key is greater than node segment, descend right
+            } else {
+                node = node.right.as_ref().as_ref()?;
+            }
+        }
+    }
+
+    pub fn floor_entry(&self, key: &K) -> Option<(&Segment<K>, &V)> {
+        let mut floor = None;
+        let mut node = Some(self);
+        while let Some(current) = node {
+            // if node starts at or before key, it is a candidate, look for a later one
+            if current.segment.lower() <= key {
+                floor = Some((&current.segment, &current.value));
+                node = current.right.as_ref().as_ref();
+            // otherwise, look for an earlier one
+            } else {
+                node = current.left.as_ref().as_ref();
+            }
+        }
+        floor
+    }
+
+    pub fn ceiling_entry(&self, key: &K) -> Option<(&Segment<K>, &V)> {
+        let mut ceiling = None;
+        let mut node = Some(self);
+        while let Some(current) = node {
+            // if node starts at or after key, it is a candidate, look for an earlier one
+            if current.segment.lower() >= key {
+                ceiling = Some((&current.segment, &current.value));
+                node = current.left.as_ref().as_ref();
+            // otherwise, look for a later one
+            } else {
+                node = current.right.as_ref().as_ref();
+            }
+        }
+        ceiling
+    }
+
+    /// Returns the first entry in order whose segment satisfies `is_after`, which must be false
+    /// for some prefix of the entries and true for the rest.
+    pub fn first_where<F>(&self, is_after: F) -> Option<(&Segment<K>, &V)>
+    where
+        F: Fn(&Segment<K>) -> bool
+    {
+        let mut first = None;
+        let mut node = Some(self);
+        while let Some(current) = node {
+            // if node satisfies the predicate, it is a candidate, look for an earlier one
+            if is_after(&current.segment) {
+                first = Some((&current.segment, &current.value));
+                node = current.left.as_ref().as_ref();
+            // otherwise, look for a later one
+            } else {
+                node = current.right.as_ref().as_ref();
+            }
+        }
+        first
+    }
+
+    /// Returns the last entry in order whose segment satisfies `is_before`, which must be true
+    /// for some prefix of the entries and false for the rest.
+    pub fn last_where<F>(&self, is_before: F) -> Option<(&Segment<K>, &V)>
+    where
+        F: Fn(&Segment<K>) -> bool
+    {
+        let mut last = None;
+        let mut node = Some(self);
+        while let Some(current) = node {
+            // if node satisfies the predicate, it is a candidate, look for a later one
+            if is_before(&current.segment) {
+                last = Some((&current.segment, &current.value));
+                node = current.right.as_ref().as_ref();
+            // otherwise, look for an earlier one
+            } else {
+                node = current.left.as_ref().as_ref();
+            }
+        }
+        last
+    }
+
+    pub fn get_exact(&self, segment: &Segment<K>) -> Option<&V> {
+        // if the segments perfectly overlap
+        if (segment.lower() == self.segment.lower()) && (segment.upper() == self.segment.upper()) {
+            Some(&self.value)
+        // if segment is less than self segment
+        } else if segment.upper() <= self.segment.lower() {
+            // if left exists, recurse
+            if let Some(left) = self.left.as_ref() {
+                left.get_exact(segment)
+            // otherwise, segment doesn't exist
+            } else { None }
+        // if segment is greater than self segment
+        } else if segment.lower() >= self.segment.upper() {
+            // if right exists, recurse
+            if let Some(right) = self.right.as_ref() {
+                right.get_exact(segment)
+            // otherwise, segment doesn't exist
+            } else { None }
+        // otherwise, segments overlap in some (non-perfect) way
+        } else { None }
+    }
+
+    pub fn get_ending_at(&self, key: &K) -> Option<(&Segment<K>, &V)> {
+        // if nonempty self segment ends at key
+        if (self.segment.upper() == key) && !self.segment.is_empty() {
             Some((&self.segment, &self.value))
-        // if key is less than self segment
-        } else if key < self.segment.lower() {
+        // if key is at or before self segment
+        } else if key <= self.segment.lower() {
             // if left exists, recurse
             if let Some(left) = self.left.as_ref() {
-                left.get_entry(key)
-            // otherwise, key doesn't exist
+                left.get_ending_at(key)
+            // otherwise, no segment ends at key
             } else { None }
-        // otherwise, key is greater than self segment
-        } else {
+        // if key is after self segment
+        } else if key > self.segment.upper() {
             // if right exists, recurse
             if let Some(right) = self.right.as_ref() {
-                right.get_entry(key)
-            // otherwise, key doesn't exist
+                right.get_ending_at(key)
+            // otherwise, no segment ends at key
+            } else { None }
+        // otherwise, key is within self segment
+        } else { None }
+    }
+
+    pub fn get_starting_at(&self, key: &K) -> Option<(&Segment<K>, &V)> {
+        // if nonempty self segment starts at key
+        if (self.segment.lower() == key) && !self.segment.is_empty() {
+            Some((&self.segment, &self.value))
+        // if key is at or after self segment
+        } else if key >= self.segment.upper() {
+            // if right exists, recurse
+            if let Some(right) = self.right.as_ref() {
+                right.get_starting_at(key)
+            // otherwise, no segment starts at key
+            } else { None }
+        // if key is before self segment
+        } else if key < self.segment.lower() {
+            // if left exists, recurse
+            if let Some(left) = self.left.as_ref() {
+                left.get_starting_at(key)
+            // otherwise, no segment starts at key
             } else { None }
+        // otherwise, key is within self segment
+        } else { None }
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
+        self.get_entry_mut(key).map(|(_, v)| v)
+    }
+
+    pub fn get_entry_mut<Q>(&mut self, key: &Q) -> Option<(&Segment<K>, &mut V)>
+    where
+        K: Borrow<Q>,
+        Q: PartialOrd + ?Sized,
+    {
+        let mut node = self;
+        loop {
+            // if node segment contains key
+            if (node.segment.lower().borrow() <= key) && (key < node.segment.upper().borrow()) {
+                return Some((&node.segment, &mut node.value));
+            // if key is less than node segment, descend left
+            } else if key < node.segment.lower().borrow() {
+                node = node.left.as_mut().as_mut()?;
+            // otherwise, key is greater than node segment, descend right
+            } else {
+                node = node.right.as_mut().as_mut()?;
+            }
         }
     }
 
-    pub fn insert(&mut self, segment: Segment<K>, value: V) {
+    pub fn count_enclosed(&self, segment: &Segment<K>) -> usize {
+        let mut count = if segment.encloses(&self.segment) { 1 } else { 0 };
+        // if segment extends to the left of self segment, recurse
+        if segment.lower() <= self.segment.lower() {
+            if let Some(left) = self.left.as_ref() {
+                count += left.count_enclosed(segment);
+            }
+        }
+        // if segment extends to the right of self segment, recurse
+        if segment.upper() >= self.segment.upper() {
+            if let Some(right) = self.right.as_ref() {
+                count += right.count_enclosed(segment);
+            }
+        }
+        count
+    }
+
+    pub fn count_overlapping(&self, segment: &Segment<K>) -> usize {
+        // only nonempty intersections count as overlapping
+        let mut count = if self.segment.intersects(segment) { 1 } else { 0 };
+        // if segment extends to the left of self segment, recurse
+        if segment.lower() < self.segment.lower() {
+            if let Some(left) = self.left.as_ref() {
+                count += left.count_overlapping(segment);
+            }
+        }
+        // if segment extends to the right of self segment, recurse
+        if segment.upper() > self.segment.upper() {
+            if let Some(right) = self.right.as_ref() {
+                count += right.count_overlapping(segment);
+            }
+        }
+        count
+    }
+
+    pub fn visit_overlapping<'a, F>(&'a self, segment: &Segment<K>, visit: &mut F)
+    where
+        F: FnMut(&'a Segment<K>, &'a V)
+    {
+        // if segment extends to the left of self segment, recurse
+        if segment.lower() < self.segment.lower() {
+            if let Some(left) = self.left.as_ref() {
+                left.visit_overlapping(segment, visit);
+            }
+        }
+        // only nonempty intersections count as overlapping
+        if (self.segment.lower() < segment.upper()) && (segment.lower() < self.segment.upper()) && !self.segment.is_empty() && !segment.is_empty() {
+            visit(&self.segment, &self.value);
+        }
+        // if segment extends to the right of self segment, recurse
+        if segment.upper() > self.segment.upper() {
+            if let Some(right) = self.right.as_ref() {
+                right.visit_overlapping(segment, visit);
+            }
+        }
+    }
+
+    pub fn insert(self, segment: Segment<K>, value: V) -> SegmentMapNode<K, V> {
+        self.insert_node(SegmentMapNode::new(segment, value, None, None))
+    }
+
+    /// Inserts a childless `node` as is, so its links are kept.
+    pub fn insert_node(mut self, node: SegmentMapNode<K, V>) -> SegmentMapNode<K, V> {
+        let segment = &node.segment;
         // if the segments perfectly overlap (this prevents inserting duplicate empty segments)
         if (segment.lower() == self.segment.lower()) && (segment.upper() == self.segment.upper()) {
             panic!("segments must not overlap");
         // if segment is less than self segment
         } else if segment.upper() <= self.segment.lower() {
             // if left exists, recurse
-            if let Some(left) = self.left.as_mut() {
-                left.insert(segment, value);
+            *self.left = Some(if let Some(left) = self.left.take() {
+                left.insert_node(node)
             // otherwise, set new left
-            } else {
-                self.left = Box::new(Some(SegmentMapNode::new(segment, value, None, None)));
-            }
+            } else { node });
         // if segment is greater than self segment
         } else if segment.lower() >= self.segment.upper() {
             // if right exists, recurse
-            if let Some(right) = self.right.as_mut() {
-                right.insert(segment, value);
+            *self.right = Some(if let Some(right) = self.right.take() {
+                right.insert_node(node)
             // otherwise, set new right
-            } else {
-                self.right = Box::new(Some(SegmentMapNode::new(segment, value, None, None)));
-            }
+            } else { node });
         // otherwise, segments overlap in some (non-perfect) way
         } else {
             panic!("segments must not overlap");
         }
+        self.balance()
     }
 }
 
 impl<K, V> SegmentMapNode<K, V> 
 where
-    K: Clone + PartialOrd,
-    V: Clone,
+    K: Clone + PartialOrd
 {
-    pub fn remove(mut self, segment: &Segment<K>) -> Option<SegmentMapNode<K, V>> {
+    pub fn clone_conflicting(&self, segment: &Segment<K>, result: &mut Vec<Segment<K>>) {
+        // if segment extends to (or touches) the left of self segment, recurse
+        if segment.lower() <= self.segment.lower() {
+            if let Some(left) = self.left.as_ref() {
+                left.clone_conflicting(segment, result);
+            }
+        }
+        // identical segments conflict (this catches duplicate empty segments), as does any other overlap
+        if ((segment.lower() == self.segment.lower()) && (segment.upper() == self.segment.upper())) || ((segment.upper() > self.segment.lower()) && (segment.lower() < self.segment.upper())) {
+            result.push(self.segment.clone());
+        }
+        // if segment extends to (or touches) the right of self segment, recurse
+        if segment.upper() >= self.segment.upper() {
+            if let Some(right) = self.right.as_ref() {
+                right.clone_conflicting(segment, result);
+            }
+        }
+    }
+
+    pub fn clone_empty_within(&self, segment: &Segment<K>, result: &mut Vec<Segment<K>>) {
+        // if segment extends to the left of self segment, recurse
+        if segment.lower() < self.segment.lower() {
+            if let Some(left) = self.left.as_ref() {
+                left.clone_empty_within(segment, result);
+            }
+        }
+        // only empty segments strictly within segment count
+        if self.segment.is_empty() && (segment.lower() < self.segment.lower()) && (self.segment.upper() < segment.upper()) {
+            result.push(self.segment.clone());
+        }
+        // if segment extends to the right of self segment, recurse
+        if segment.upper() > self.segment.upper() {
+            if let Some(right) = self.right.as_ref() {
+                right.clone_empty_within(segment, result);
+            }
+        }
+    }
+
+    /// Removes `segment` like `remove`, but calls `split` for a copy of the value when a segment is
+    /// cut in two, rather than requiring `V: Clone`.
+    pub fn remove_with<F>(mut self, segment: &Segment<K>, split: &F, len: &mut usize) -> Option<SegmentMapNode<K, V>>
+    where
+        F: Fn(&V) -> V
+    {
         // empty segments can be removed
         if segment.is_empty() {
             // if empty segment is enclosed by self segment, (potentially) split the segment
-            if self.segment.encloses(&segment) {
+            if self.segment.encloses(segment) {
                 // if empty segment exactly equals self segment
                 if (segment.lower() == self.segment.lower()) && (segment.upper() == self.segment.upper()) {
+                    *len -= 1;
                     // remove self
                     match (*self.left, *self.right) {
                         // two children, replace with right minimum
                         (Some(left), Some(right)) => {
                             let (right, mut result) = right.remove_min_node();
-                            result.right = Box::new(right);
-                            result.left = Box::new(Some(left));
-                            Some(result)
+                            *result.right = right;
+                            *result.left = Some(left);
+                            Some(result.balance())
                         },
                         // one left child, move up
                         (Some(left), None) => Some(left),
@@ -144,29 +701,30 @@ where
                 // if empty segment is touching left side of nonempty self segment, do not remove self
                 } else if segment.lower() == self.segment.lower() {
                     // if left exists, recurse
-                    self.left = Box::new(if let Some(left) = self.left.take() {
-                        left.remove(segment)
+                    *self.left = if let Some(left) = self.left.take() {
+                        left.remove_with(segment, split, len)
                     // otherwise, nothing to remove
-                    } else { None });
-                    Some(self)
+                    } else { None };
+                    Some(self.balance())
                 // if empty segment is touching right side of nonempty self segment, do not remove self
                 } else if segment.upper() == self.segment.upper() {
                     // if right exists, recurse
-                    self.right = Box::new(if let Some(right) = self.right.take() {
-                        right.remove(segment)
+                    *self.right = if let Some(right) = self.right.take() {
+                        right.remove_with(segment, split, len)
                     // otherwise, nothing to remove
-                    } else { None });
-                    Some(self)
+                    } else { None };
+                    Some(self.balance())
                 // otherwise, empty segment is within self segment
                 } else {
+                    *len -= 1;
                     // remove self, will reinsert each side of split
                     let mut result = match (*self.left, *self.right) {
                         // two children, replace with right minimum
                         (Some(left), Some(right)) => {
                             let (right, mut result) = right.remove_min_node();
-                            result.right = Box::new(right);
-                            result.left = Box::new(Some(left));
-                            Some(result)
+                            *result.right = right;
+                            *result.left = Some(left);
+                            Some(result.balance())
                         },
                         // one left child, move up
                         (Some(left), None) => Some(left),
@@ -177,67 +735,76 @@ where
                     };
                     // reinsert left part of segment
                     let left_segment = Segment::new(self.segment.lower().clone(), segment.lower().clone());
+                    *len += 1;
                     // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(left_segment, self.value.clone());
+                    result = Some(if let Some(result) = result {
+                        result.insert(left_segment, split(&self.value))
                     // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(left_segment, self.value.clone(), None, None));
-                    }
+                    } else { SegmentMapNode::new(left_segment, split(&self.value), None, None) });
                     // reinsert right part of segment
                     let right_segment = Segment::new(segment.upper().clone(), self.segment.upper().clone());
+                    *len += 1;
                     // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(right_segment, self.value.clone());
+                    result = Some(if let Some(result) = result {
+                        result.insert(right_segment, self.value)
                     // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(right_segment, self.value.clone(), None, None));
-                    }
+                    } else { SegmentMapNode::new(right_segment, self.value, None, None) });
                     result
                 }
             // if empty segment is less than self segment, recurse
             } else if segment.upper() < self.segment.lower() {
                 // if left exists, recurse
                 if let Some(left) = self.left.take() {
-                    self.left = Box::new(left.remove(segment));
+                    *self.left = left.remove_with(segment, split, len);
                 } // otherwise, nothing to remove
-                Some(self)
+                Some(self.balance())
             // otherwise, empty segment is greater than self segment, recurse
             } else {
                 // if right exists, recurse
                 if let Some(right) = self.right.take() {
-                    self.right = Box::new(right.remove(segment));
+                    *self.right = right.remove_with(segment, split, len);
                 } // otherwise, nothing to remove
-                Some(self)
+                Some(self.balance())
             }
         // if the segments overlap
-        } else if let Some(intersection) = segment.intersection(&self.segment) {
+        } else if let Some(intersection) = overlap(segment, &self.segment) {
             // if the overlap is empty, handle specially to prevent infinite recursion
             if intersection.is_empty() {
+                // if empty self segment is strictly within segment, split segment around it
+                if (segment.lower() < self.segment.lower()) && (self.segment.upper() < segment.upper()) {
+                    // if left exists, recurse with left part
+                    if let Some(left) = self.left.take() {
+                        *self.left = left.remove_with(&Segment::new(segment.lower().clone(), self.segment.lower().clone()), split, len);
+                    } // otherwise, nothing to remove
+                    // if right exists, recurse with right part
+                    if let Some(right) = self.right.take() {
+                        *self.right = right.remove_with(&Segment::new(self.segment.upper().clone(), segment.upper().clone()), split, len);
+                    } // otherwise, nothing to remove
                 // if segment is touching the right
-                if segment.lower() == self.segment.upper() {
+                } else if segment.lower() == self.segment.upper() {
                     // if right exists, recurse
                     if let Some(right) = self.right.take() {
-                        self.right = Box::new(right.remove(segment));
+                        *self.right = right.remove_with(segment, split, len);
                     } // otherwise, nothing to remove
                 // otherwise, segment is touching the left
                 } else {
                     // if left exists, recurse
                     if let Some(left) = self.left.take() {
-                        self.left = Box::new(left.remove(segment));
+                        *self.left = left.remove_with(segment, split, len);
                     } // otherwise, nothing to remove
                 }
-                Some(self)
+                Some(self.balance())
             // otherwise, the overlap must be removed
             } else {
+                *len -= 1;
                 // remove self, will reinsert as needed
                 let mut result = match (*self.left, *self.right) {
                     // two children, replace with right minimum
                     (Some(left), Some(right)) => {
                         let (right, mut result) = right.remove_min_node();
-                        result.right = Box::new(right);
-                        result.left = Box::new(Some(left));
-                        Some(result)
+                        *result.right = right;
+                        *result.left = Some(left);
+                        Some(result.balance())
                     },
                     // one left child, move up
                     (Some(left), None) => Some(left),
@@ -246,41 +813,46 @@ where
                     // no children, simply remove
                     (None, None) => None,
                 };
+                // if both parts of self still exist, split the value between them
+                let (left_value, right_value) = match (self.segment.lower() < intersection.lower(), self.segment.upper() > intersection.upper()) {
+                    (true, true) => (Some(split(&self.value)), Some(self.value)),
+                    (true, false) => (Some(self.value), None),
+                    (false, true) => (None, Some(self.value)),
+                    (false, false) => (None, None),
+                };
                 // if left part of segment still needs to be removed
                 if segment.lower() < intersection.lower() {
                     // if result exists, do plain remove
                     result = if let Some(result) = result {
-                        result.remove(&Segment::new(segment.lower().clone(), intersection.lower().clone()))
+                        result.remove_with(&Segment::new(segment.lower().clone(), intersection.lower().clone()), split, len)
                     // otherwise, nothing to remove
                     } else { None };
                 // if left part of self still exists, reinsert
-                } else if self.segment.lower() < intersection.lower() {
+                } else if let Some(value) = left_value {
                     let segment = Segment::new(self.segment.lower().clone(), intersection.lower().clone());
+                    *len += 1;
                     // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(segment, self.value.clone());
+                    result = Some(if let Some(result) = result {
+                        result.insert(segment, value)
                     // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(segment, self.value.clone(), None, None));
-                    }
+                    } else { SegmentMapNode::new(segment, value, None, None) });
                 }
                 // if right part of segment still needs to be removed
                 if segment.upper() > intersection.upper() {
                     // if result exists, do plain remove
                     result = if let Some(result) = result {
-                        result.remove(&Segment::new(intersection.upper().clone(), segment.upper().clone()))
+                        result.remove_with(&Segment::new(intersection.upper().clone(), segment.upper().clone()), split, len)
                     // otherwise, nothing to remove
                     } else { None };
                 // if right part of self still exists, reinsert
-                } else if self.segment.upper() > intersection.upper() {
+                } else if let Some(value) = right_value {
                     let segment = Segment::new(intersection.upper().clone(), self.segment.upper().clone());
+                    *len += 1;
                     // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(segment, self.value);
+                    result = Some(if let Some(result) = result {
+                        result.insert(segment, value)
                     // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(segment, self.value, None, None));
-                    }
+                    } else { SegmentMapNode::new(segment, value, None, None) });
                 }
                 result
             }
@@ -290,44 +862,74 @@ where
             if segment.lower() > self.segment.upper() {
                 // if right exists, recurse
                 if let Some(right) = self.right.take() {
-                    self.right = Box::new(right.remove(segment));
+                    *self.right = right.remove_with(segment, split, len);
                 } // otherwise, there is nothing to remove
             // otherwise segment is less than self segment
             } else {
                 // if left exists, recurse
                 if let Some(left) = self.left.take() {
-                    self.left = Box::new(left.remove(segment));
+                    *self.left = left.remove_with(segment, split, len);
                 } // otherwise, there is nothing to remove
             }
-            Some(self)
+            Some(self.balance())
         }
     }
+}
 
-    pub fn update<F>(self, segment: &Segment<K>, value: F) -> Option<SegmentMapNode<K, V>>
+impl<K, V> SegmentMapNode<K, V> 
+where
+    K: Clone + PartialOrd,
+    V: Clone,
+{
+    pub fn clone_overlapping(&self, segment: &Segment<K>, result: &mut Vec<(Segment<K>, V)>) {
+        // if segment extends to the left of self segment, recurse
+        if segment.lower() < self.segment.lower() {
+            if let Some(left) = self.left.as_ref() {
+                left.clone_overlapping(segment, result);
+            }
+        }
+        // if the overlap is nonempty, clone it
+        if let Some(intersection) = segment.intersection(&self.segment) {
+            result.push((intersection, self.value.clone()));
+        }
+        // if segment extends to the right of self segment, recurse
+        if segment.upper() > self.segment.upper() {
+            if let Some(right) = self.right.as_ref() {
+                right.clone_overlapping(segment, result);
+            }
+        }
+    }
+
+    pub fn remove(self, segment: &Segment<K>, len: &mut usize) -> Option<SegmentMapNode<K, V>> {
+        self.remove_with(segment, &V::clone, len)
+    }
+
+    pub fn update<F>(self, segment: &Segment<K>, value: &mut F, len: &mut usize) -> Option<SegmentMapNode<K, V>>
     where
-        F: Fn(Option<V>) -> Option<V> + Clone
+        F: FnMut(Option<V>) -> Option<V>
     {
-        self.update_entry(segment, |_, v| value(v))
+        self.update_entry(segment, &mut |_, v| value(v), len)
     }
 
-    pub fn update_entry<F>(mut self, segment: &Segment<K>, value: F) -> Option<SegmentMapNode<K, V>>
+    pub fn update_entry<F>(mut self, segment: &Segment<K>, value: &mut F, len: &mut usize) -> Option<SegmentMapNode<K, V>>
     where
-        F: Fn(&Segment<K>, Option<V>) -> Option<V> + Clone
+        F: FnMut(&Segment<K>, Option<V>) -> Option<V>
     {
         // empty segments can be updated
         if segment.is_empty() {
             // if empty segment is enclosed by self segment, (potentially) split the segment
-            if self.segment.encloses(&segment) {
+            if self.segment.encloses(segment) {
                 // if empty segment exactly equals self segment
                 if (segment.lower() == self.segment.lower()) && (segment.upper() == self.segment.upper()) {
+                    *len -= 1;
                     // remove self, will reinsert as needed
                     let mut result = match (*self.left, *self.right) {
                         // two children, replace with right minimum
                         (Some(left), Some(right)) => {
                             let (right, mut result) = right.remove_min_node();
-                            result.right = Box::new(right);
-                            result.left = Box::new(Some(left));
-                            Some(result)
+                            *result.right = right;
+                            *result.left = Some(left);
+                            Some(result.balance())
                         },
                         // one left child, move up
                         (Some(left), None) => Some(left),
@@ -337,46 +939,48 @@ where
                         (None, None) => None,
                     };
                     // if update produces a value, reinsert
-                    if let Some(value) = value(&segment, Some(self.value.clone())) {
+                    if let Some(value) = value(segment, Some(self.value.clone())) {
+                        *len += 1;
                         // if result exists, do plain insert
-                        if let Some(result) = result.as_mut() {
-                            result.insert(segment.clone(), value);
+                        result = Some(if let Some(result) = result {
+                            result.insert(segment.clone(), value)
                         // otherwise, this is the new result
-                        } else {
-                            result = Some(SegmentMapNode::new(segment.clone(), value, None, None));
-                        }
+                        } else { SegmentMapNode::new(segment.clone(), value, None, None) });
                     };
                     result
                 // if empty segment is touching left side of nonempty self segment, do not remove self
                 } else if segment.lower() == self.segment.lower() {
                     // if left exists, recurse
                     if let Some(left) = self.left.take() {
-                        self.left = Box::new(left.update_entry(segment, value));
+                        *self.left = left.update_entry(segment, value, len);
                     // otherwise, if update produces a value, this is the new result
                     } else if let Some(value) = value(segment, None) {
-                        self.left = Box::new(Some(SegmentMapNode::new(segment.clone(), value, None, None)));
+                        *len += 1;
+                        *self.left = Some(SegmentMapNode::new(segment.clone(), value, None, None));
                     }
-                    Some(self)
+                    Some(self.balance())
                 // if empty segment is touching right side of nonempty self segment, do not remove self
                 } else if segment.upper() == self.segment.upper() {
                     // if right exists, recurse
                     if let Some(right) = self.right.take() {
-                        self.right = Box::new(right.update_entry(segment, value));
+                        *self.right = right.update_entry(segment, value, len);
                     // otherwise, if update produces a value, this is the new result
                     } else if let Some(value) = value(segment, None) {
-                        self.right = Box::new(Some(SegmentMapNode::new(segment.clone(), value, None, None)));
+                        *len += 1;
+                        *self.right = Some(SegmentMapNode::new(segment.clone(), value, None, None));
                     }
-                    Some(self)
+                    Some(self.balance())
                 // otherwise, empty segment is within self segment
                 } else {
+                    *len -= 1;
                     // remove self, will reinsert each side of split
                     let mut result = match (*self.left, *self.right) {
                         // two children, replace with right minimum
                         (Some(left), Some(right)) => {
                             let (right, mut result) = right.remove_min_node();
-                            result.right = Box::new(right);
-                            result.left = Box::new(Some(left));
-                            Some(result)
+                            *result.right = right;
+                            *result.left = Some(left);
+                            Some(result.balance())
                         },
                         // one left child, move up
                         (Some(left), None) => Some(left),
@@ -386,90 +990,112 @@ where
                         (None, None) => None,
                     };
                     // if update produces a value, reinsert
-                    if let Some(value) = value(&segment, Some(self.value.clone())) {
+                    if let Some(value) = value(segment, Some(self.value.clone())) {
+                        *len += 1;
                         // if result exists, do plain insert
-                        if let Some(result) = result.as_mut() {
-                            result.insert(segment.clone(), value);
+                        result = Some(if let Some(result) = result {
+                            result.insert(segment.clone(), value)
                         // otherwise, this is the new result
-                        } else {
-                            result = Some(SegmentMapNode::new(segment.clone(), value, None, None));
-                        }
+                        } else { SegmentMapNode::new(segment.clone(), value, None, None) });
                     };
                     // reinsert left part of segment
                     let left_segment = Segment::new(self.segment.lower().clone(), segment.lower().clone());
+                    *len += 1;
                     // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(left_segment, self.value.clone());
+                    result = Some(if let Some(result) = result {
+                        result.insert(left_segment, self.value.clone())
                     // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(left_segment, self.value.clone(), None, None));
-                    }
+                    } else { SegmentMapNode::new(left_segment, self.value.clone(), None, None) });
                     // reinsert right part of segment
                     let right_segment = Segment::new(segment.upper().clone(), self.segment.upper().clone());
+                    *len += 1;
                     // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(right_segment, self.value.clone());
+                    result = Some(if let Some(result) = result {
+                        result.insert(right_segment, self.value.clone())
                     // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(right_segment, self.value.clone(), None, None));
-                    }
+                    } else { SegmentMapNode::new(right_segment, self.value.clone(), None, None) });
                     result
                 }
             // if empty segment is less than self segment, recurse
             } else if segment.upper() < self.segment.lower() {
                 // if left exists, recurse
                 if let Some(left) = self.left.take() {
-                    self.left = Box::new(left.update_entry(segment, value));
+                    *self.left = left.update_entry(segment, value, len);
                 // otherwise, if update produces a value, this is the new result
                 } else if let Some(value) = value(segment, None) {
-                    self.left = Box::new(Some(SegmentMapNode::new(segment.clone(), value, None, None)));
+                    *len += 1;
+                    *self.left = Some(SegmentMapNode::new(segment.clone(), value, None, None));
                 }
-                Some(self)
+                Some(self.balance())
             // otherwise, empty segment is greater than self segment, recurse
             } else {
                 // if right exists, recurse
                 if let Some(right) = self.right.take() {
-                    self.right = Box::new(right.update_entry(segment, value));
+                    *self.right = right.update_entry(segment, value, len);
                 // otherwise, if update produces a value, this is the new result
                 } else if let Some(value) = value(segment, None) {
-                    self.right = Box::new(Some(SegmentMapNode::new(segment.clone(), value, None, None)));
+                    *len += 1;
+                    *self.right = Some(SegmentMapNode::new(segment.clone(), value, None, None));
                 }
-                Some(self)
+                Some(self.balance())
             }
         // if the segments overlap
-        } else if let Some(intersection) = segment.intersection(&self.segment) {
+        } else if let Some(intersection) = overlap(segment, &self.segment) {
             // if the overlap is empty, handle specially to prevent infinite recursion
             if intersection.is_empty() {
+                // if empty self segment is strictly within segment, split segment around it
+                if (segment.lower() < self.segment.lower()) && (self.segment.upper() < segment.upper()) {
+                    let left_segment = Segment::new(segment.lower().clone(), self.segment.lower().clone());
+                    // if left exists, recurse with left part
+                    if let Some(left) = self.left.take() {
+                        *self.left = left.update_entry(&left_segment, value, len);
+                    // otherwise, if update produces a value, this is the new left
+                    } else if let Some(value) = value(&left_segment, None) {
+                        *len += 1;
+                        *self.left = Some(SegmentMapNode::new(left_segment, value, None, None));
+                    }
+                    let right_segment = Segment::new(self.segment.upper().clone(), segment.upper().clone());
+                    // if right exists, recurse with right part
+                    if let Some(right) = self.right.take() {
+                        *self.right = right.update_entry(&right_segment, value, len);
+                    // otherwise, if update produces a value, this is the new right
+                    } else if let Some(value) = value(&right_segment, None) {
+                        *len += 1;
+                        *self.right = Some(SegmentMapNode::new(right_segment, value, None, None));
+                    }
                 // if segment is touching the right
-                if segment.lower() == self.segment.upper() {
+                } else if segment.lower() == self.segment.upper() {
                     // if right exists, recurse
                     if let Some(right) = self.right.take() {
-                        self.right = Box::new(right.update_entry(segment, value));
+                        *self.right = right.update_entry(segment, value, len);
                     // otherwise, if update produces a value, this is the new right
                     } else if let Some(value) = value(segment, None) {
-                        self.right = Box::new(Some(SegmentMapNode::new(segment.clone(), value, None, None)));
+                        *len += 1;
+                        *self.right = Some(SegmentMapNode::new(segment.clone(), value, None, None));
                     }
                 // otherwise, segment is touching the left
                 } else {
                     // if left exists, recurse
                     if let Some(left) = self.left.take() {
-                        self.left = Box::new(left.update_entry(segment, value));
+                        *self.left = left.update_entry(segment, value, len);
                     // otherwise, if update produces a value, this is the new left
                     } else if let Some(value) = value(segment, None) {
-                        self.left = Box::new(Some(SegmentMapNode::new(segment.clone(), value, None, None)));
+                        *len += 1;
+                        *self.left = Some(SegmentMapNode::new(segment.clone(), value, None, None));
                     }
                 }
-                Some(self)
+                Some(self.balance())
             // otherwise, the overlap must be updated
             } else {
+                *len -= 1;
                 // remove self, will reinsert as needed
                 let mut result = match (*self.left, *self.right) {
                     // two children, replace with right minimum
                     (Some(left), Some(right)) => {
                         let (right, mut result) = right.remove_min_node();
-                        result.right = Box::new(right);
-                        result.left = Box::new(Some(left));
-                        Some(result)
+                        *result.right = right;
+                        *result.left = Some(left);
+                        Some(result.balance())
                     },
                     // one left child, move up
                     (Some(left), None) => Some(left),
@@ -478,59 +1104,60 @@ where
                     // no children, simply remove
                     (None, None) => None,
                 };
-                // if update produces a value, reinsert intersection
-                if let Some(value) = value(&intersection, Some(self.value.clone())) {
-                    // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(intersection.clone(), value);
-                    // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(intersection.clone(), value, None, None));
-                    }
-                }
                 // if left part of segment still needs to be updated
                 if segment.lower() < intersection.lower() {
                     let segment = Segment::new(segment.lower().clone(), intersection.lower().clone());
                     // if result exists, do plain update
                     result = if let Some(result) = result {
-                        result.update_entry(&segment, value.clone())
+                        result.update_entry(&segment, value, len)
                     // otherwise, if update produces a value, this is the new result
-                    } else if let Some(value) = value(&segment, None) {
-                        Some(SegmentMapNode::new(segment, value, None, None))
-                    // otherwise, no result
-                    } else { None }
+                    } else {
+                        value(&segment, None).map(|value| {
+                            *len += 1;
+                            SegmentMapNode::new(segment, value, None, None)
+                        })
+                    }
                 // if left part of self still exists, reinsert
                 } else if self.segment.lower() < intersection.lower() {
                     let segment = Segment::new(self.segment.lower().clone(), intersection.lower().clone());
+                    *len += 1;
                     // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(segment, self.value.clone());
+                    result = Some(if let Some(result) = result {
+                        result.insert(segment, self.value.clone())
                     // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(segment, self.value.clone(), None, None));
-                    }
+                    } else { SegmentMapNode::new(segment, self.value.clone(), None, None) });
+                }
+                // if update produces a value, reinsert intersection (after the left part, so pieces are visited in order)
+                if let Some(value) = value(&intersection, Some(self.value.clone())) {
+                    *len += 1;
+                    // if result exists, do plain insert
+                    result = Some(if let Some(result) = result {
+                        result.insert(intersection.clone(), value)
+                    // otherwise, this is the new result
+                    } else { SegmentMapNode::new(intersection.clone(), value, None, None) });
                 }
                 // if right part of segment still needs to be updated
                 if segment.upper() > intersection.upper() {
                     let segment = Segment::new(intersection.upper().clone(), segment.upper().clone());
                     // if result exists, do plain update
                     result = if let Some(result) = result {
-                        result.update_entry(&segment, value)
+                        result.update_entry(&segment, value, len)
                     // otherwise, if update produces value, this is the new result
-                    } else if let Some(value) = value(&segment, None) {
-                        Some(SegmentMapNode::new(segment, value, None, None))
-                    // otherwise, no result
-                    } else { None }
+                    } else {
+                        value(&segment, None).map(|value| {
+                            *len += 1;
+                            SegmentMapNode::new(segment, value, None, None)
+                        })
+                    }
                 // if right part of self still exists, reinsert
                 } else if self.segment.upper() > intersection.upper() {
                     let segment = Segment::new(intersection.upper().clone(), self.segment.upper().clone());
+                    *len += 1;
                     // if result exists, do plain insert
-                    if let Some(result) = result.as_mut() {
-                        result.insert(segment, self.value);
+                    result = Some(if let Some(result) = result {
+                        result.insert(segment, self.value)
                     // otherwise, this is the new result
-                    } else {
-                        result = Some(SegmentMapNode::new(segment, self.value, None, None));
-                    }
+                    } else { SegmentMapNode::new(segment, self.value, None, None) });
                 }
                 result
             }
@@ -540,22 +1167,24 @@ where
             if segment.lower() > self.segment.upper() {
                 // if right exists, recurse
                 if let Some(right) = self.right.take() {
-                    self.right = Box::new(right.update_entry(segment, value));
+                    *self.right = right.update_entry(segment, value, len);
                 // otherwise, if update produces value, this is the new right
                 } else if let Some(value) = value(segment, None) {
-                    self.right = Box::new(Some(SegmentMapNode::new(segment.clone(), value, None, None)));
+                    *len += 1;
+                    *self.right = Some(SegmentMapNode::new(segment.clone(), value, None, None));
                 }
             // otherwise, segment is less than self segment
             } else {
                 // if left exists, recurse
                 if let Some(left) = self.left.take() {
-                    self.left = Box::new(left.update_entry(segment, value));
+                    *self.left = left.update_entry(segment, value, len);
                 // otherwise, if update produces value, this is the new right
                 } else if let Some(value) = value(segment, None) {
-                    self.left = Box::new(Some(SegmentMapNode::new(segment.clone(), value, None, None)));
+                    *len += 1;
+                    *self.left = Some(SegmentMapNode::new(segment.clone(), value, None, None));
                 }
             }
-            Some(self)
+            Some(self.balance())
         }
     }
 }
@@ -0,0 +1,170 @@
+use crate::{
+    Segment,
+    SegmentMap,
+    Segments,
+};
+
+/// A set of keys stored as the maximal disjoint segments covering them.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SegmentSet<K> {
+    map: SegmentMap<K, ()>,
+}
+
+impl<K> SegmentSet<K>
+where
+    K: PartialOrd
+{
+    pub fn new() -> SegmentSet<K> {
+        SegmentSet { map: SegmentMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Iterates over the maximal segments of the set in ascending order.
+    pub fn segments(&self) -> Segments<'_, K, ()> {
+        self.map.segments()
+    }
+}
+
+impl<K> SegmentSet<K>
+where
+    K: Clone + PartialOrd
+{
+    // merge abutting pieces so only maximal segments are stored
+    fn coalesced(map: SegmentMap<K, ()>) -> SegmentSet<K> {
+        SegmentSet { map: SegmentMap::from_sorted(map.into_coalesced()) }
+    }
+
+    /// Adds every key of `segment`, merging it with the segments it overlaps or touches. An empty
+    /// segment has no keys, so it is ignored.
+    pub fn insert(&mut self, segment: Segment<K>) {
+        if !segment.is_empty() {
+            self.map.insert_coalesce(segment, ());
+        }
+    }
+
+    /// Removes every key of `segment`, splitting the segments it lies within. An empty segment has
+    /// no keys, so it is ignored.
+    pub fn remove(&mut self, segment: &Segment<K>) {
+        if !segment.is_empty() {
+            self.map.remove(segment);
+        }
+    }
+
+    pub fn union(&self, other: &SegmentSet<K>) -> SegmentSet<K> {
+        SegmentSet::coalesced(self.map.union_with(&other.map, |_, _| ()))
+    }
+
+    pub fn intersection(&self, other: &SegmentSet<K>) -> SegmentSet<K> {
+        SegmentSet::coalesced(self.map.intersect_with(&other.map, |_, _| ()))
+    }
+
+    pub fn difference(&self, other: &SegmentSet<K>) -> SegmentSet<K> {
+        let mut difference = self.clone();
+        for segment in other.segments() {
+            difference.remove(segment);
+        }
+        difference
+    }
+}
+
+impl<K> Default for SegmentSet<K>
+where
+    K: PartialOrd
+{
+    fn default() -> SegmentSet<K> {
+        SegmentSet::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Segment,
+        SegmentSet,
+    };
+
+    fn segment_set(segments: Vec<Segment<u32>>) -> SegmentSet<u32> {
+        let mut segment_set = SegmentSet::new();
+        for segment in segments {
+            segment_set.insert(segment);
+        }
+        segment_set
+    }
+
+    #[test]
+    fn test_insert() {
+        // [----)---[----)
+        let mut segment_set = segment_set(vec![Segment::new(0, 6), Segment::new(9, 15)]);
+        assert_eq!(2, segment_set.len());
+        assert!(segment_set.contains(&0));
+        assert!(!segment_set.contains(&6));
+
+        // [-------------)
+        segment_set.insert(Segment::new(6, 9));
+        assert_eq!(vec![&Segment::new(0, 15)], segment_set.segments().collect::<Vec<_>>());
+
+        // [----------------)
+        segment_set.insert(Segment::new(12, 18));
+        segment_set.insert(Segment::new(3, 3));
+        assert_eq!(vec![&Segment::new(0, 18)], segment_set.segments().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_remove() {
+        // [----------------)
+        let mut segment_set = segment_set(vec![Segment::new(0, 18)]);
+
+        // [----)---[-------)
+        segment_set.remove(&Segment::new(6, 9));
+        assert_eq!(vec![
+            &Segment::new(0, 6),
+            &Segment::new(9, 18),
+        ], segment_set.segments().collect::<Vec<_>>());
+        assert!(!segment_set.contains(&7));
+
+        // [----)---[-------)
+        segment_set.remove(&Segment::new(12, 12));
+        assert_eq!(vec![
+            &Segment::new(0, 6),
+            &Segment::new(9, 18),
+        ], segment_set.segments().collect::<Vec<_>>());
+        assert!(segment_set.contains(&12));
+    }
+
+    #[test]
+    fn test_set_operations() {
+        // [----)---[----)
+        // ---[-----)---[----)
+        let left = segment_set(vec![Segment::new(0, 6), Segment::new(9, 15)]);
+        let right = segment_set(vec![Segment::new(3, 9), Segment::new(12, 18)]);
+
+        // [-----------------)
+        assert_eq!(vec![&Segment::new(0, 18)], left.union(&right).segments().collect::<Vec<_>>());
+
+        // ---[-)------[-)
+        assert_eq!(vec![
+            &Segment::new(3, 6),
+            &Segment::new(12, 15),
+        ], left.intersection(&right).segments().collect::<Vec<_>>());
+
+        // [-)------[-)
+        assert_eq!(vec![
+            &Segment::new(0, 3),
+            &Segment::new(9, 12),
+        ], left.difference(&right).segments().collect::<Vec<_>>());
+
+        assert!(left.intersection(&SegmentSet::new()).is_empty());
+        assert_eq!(left, left.union(&SegmentSet::new()));
+    }
+}
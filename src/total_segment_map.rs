@@ -0,0 +1,127 @@
+use crate::{
+    Segment,
+    SegmentMap,
+    Span,
+    Spans,
+};
+
+/// A `SegmentMap` treated as a total function, with a default value standing in for every gap.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TotalSegmentMap<K, V> {
+    map: SegmentMap<K, V>,
+    default: V,
+}
+
+impl<K, V> TotalSegmentMap<K, V>
+where
+    K: PartialOrd
+{
+    pub fn new(default: V) -> TotalSegmentMap<K, V> {
+        TotalSegmentMap { map: SegmentMap::new(), default }
+    }
+
+    pub fn from_map(map: SegmentMap<K, V>, default: V) -> TotalSegmentMap<K, V> {
+        TotalSegmentMap { map, default }
+    }
+
+    pub fn into_map(self) -> SegmentMap<K, V> {
+        self.map
+    }
+
+    pub fn map(&self) -> &SegmentMap<K, V> {
+        &self.map
+    }
+
+    pub fn default(&self) -> &V {
+        &self.default
+    }
+
+    pub fn get(&self, key: &K) -> &V {
+        self.map.get(key).unwrap_or(&self.default)
+    }
+}
+
+impl<K, V> TotalSegmentMap<K, V>
+where
+    K: Clone + PartialOrd,
+    V: Clone,
+{
+    pub fn insert(&mut self, segment: Segment<K>, value: V) {
+        self.map.insert(segment, value);
+    }
+
+    pub fn remove(&mut self, segment: &Segment<K>) {
+        self.map.remove(segment);
+    }
+}
+
+impl<K, V> TotalSegmentMap<K, V>
+where
+    K: Clone + PartialOrd
+{
+    /// Iterates over the span of the map, yielding the default value for every gap.
+    pub fn iter_dense(&self) -> IterDense<'_, K, V> {
+        IterDense {
+            inner: self.map.iter_spans(),
+            default: &self.default,
+        }
+    }
+}
+
+pub struct IterDense<'a, K, V> {
+    inner: Spans<'a, K, V>,
+    default: &'a V,
+}
+
+impl<'a, K, V> Iterator for IterDense<'a, K, V>
+where
+    K: Clone + PartialOrd
+{
+    type Item = (Segment<K>, &'a V);
+
+    fn next(&mut self) -> Option<(Segment<K>, &'a V)> {
+        match self.inner.next()? {
+            Span::Covered(segment, value) => Some((segment.clone(), value)),
+            Span::Gap(segment) => Some((segment, self.default)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Segment,
+        TotalSegmentMap,
+    };
+
+    #[test]
+    fn test_get() {
+        // [a----)---[b----)
+        let mut total_segment_map = TotalSegmentMap::new('z');
+        total_segment_map.insert(Segment::new(0, 6), 'a');
+        total_segment_map.insert(Segment::new(9, 15), 'b');
+
+        assert_eq!(&'a', total_segment_map.get(&3));
+        assert_eq!(&'z', total_segment_map.get(&6));
+        assert_eq!(&'b', total_segment_map.get(&9));
+        assert_eq!(&'z', total_segment_map.get(&15));
+
+        // [a-)------[b----)
+        total_segment_map.remove(&Segment::new(3, 6));
+        assert_eq!(&'z', total_segment_map.get(&3));
+    }
+
+    #[test]
+    fn test_iter_dense() {
+        // [a----)---[b----)
+        let mut total_segment_map = TotalSegmentMap::new('z');
+        total_segment_map.insert(Segment::new(0, 6), 'a');
+        total_segment_map.insert(Segment::new(9, 15), 'b');
+
+        assert_eq!(vec![
+            (Segment::new(0, 6), &'a'),
+            (Segment::new(6, 9), &'z'),
+            (Segment::new(9, 15), &'b'),
+        ], total_segment_map.iter_dense().collect::<Vec<_>>());
+    }
+}